@@ -1,58 +1,30 @@
-use rocket::{info_, get, routes, response::Redirect};
-use rocket_airlock::Airlock;
-use thiserror::Error;
-use user::User;
-
-mod hatch;
-mod user;
+use rocket::{get, response::content::RawHtml, routes};
+use rocket_airlock::{Airlock, Authenticated, RedirectToLogin, oidc::OidcHatch};
 
 
 #[get("/")]
-fn index(user: User) -> String {
-    format!("Hello user: {}", user.name)
+fn index(user: Authenticated<OidcHatch, RedirectToLogin>) -> RawHtml<String> {
+    // The hidden iframe logs the user out here as well, when they log out at Keycloak.
+    RawHtml(format!(
+        r#"Hello user: {}, <a href="/logout">log out</a><iframe src="/check-session" hidden></iframe>"#,
+        user.preferred_username.as_deref().unwrap_or(&user.subject).replace('&', "&amp;").replace('<', "&lt;"),
+    ))
+}
+
+// Requested before the login, the user returns here after it instead of to the index.
+#[get("/profile")]
+fn profile(user: Authenticated<OidcHatch, RedirectToLogin>) -> String {
+    format!("Subject: {}, email: {}", user.subject, user.email.as_deref().unwrap_or("unknown"))
 }
 
-#[get("/", rank = 2)]
-fn index_anon() -> Redirect {
-    info_!("Anonymous user requested / -> redirecting to /login");
-    Redirect::to("/login")
+#[get("/goodbye")]
+fn goodbye() -> &'static str {
+    "You have been logged out."
 }
 
 #[rocket::launch]
 fn rocket() -> _ {
     rocket::build()
-        .mount("/", routes![index, index_anon])
-        .attach(Airlock::<hatch::OidcHatch>::fairing())
-}
-
-type DiscoveryError = openidconnect::DiscoveryError<openidconnect::reqwest::AsyncHttpClientError>;
-
-#[derive(Debug, Error)]
-pub enum Error {
-    #[error("Hatch")]
-    Hatch,
-    #[error("{0}")]
-    Anyhow(anyhow::Error),
-    #[error("{0}")]
-    OidcDiscovery(DiscoveryError),
-    #[error("{0}")]
-    Figment(rocket::figment::Error),
-}
-
-impl From<anyhow::Error> for Error {
-    fn from(value: anyhow::Error) -> Self {
-        Error::Anyhow(value)
-    }
-}
-
-impl From<rocket::figment::Error> for Error {
-    fn from(value: rocket::figment::Error) -> Self {
-        Error::Figment(value)
-    }
-}
-
-impl From<DiscoveryError> for Error {
-    fn from(value: DiscoveryError) -> Self {
-        Error::OidcDiscovery(value)
-    }
+        .mount("/", routes![index, profile, goodbye])
+        .attach(Airlock::<OidcHatch>::fairing())
 }
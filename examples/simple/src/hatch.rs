@@ -1,13 +1,15 @@
-use rocket_airlock::{Airlock, Hatch, Result as HatchResult};
+use rocket_airlock::{Airlock, Hatch, Result as HatchResult, ReturnTo};
 use rocket::{
-    Build, info_, Rocket, Route,
-    http::{Cookie, CookieJar, SameSite, Status},
+    Build, info_, Request, Rocket, Route,
+    http::{uri::Origin, Cookie, CookieJar, SameSite, Status},
     response::Redirect,
 };
 use serde::Deserialize;
+use crate::user::User;
 
 pub struct SimpleHatch {
-    valid_user: String
+    valid_user: String,
+    roles: Vec<String>,
 }
 
 impl SimpleHatch {
@@ -31,6 +33,7 @@ impl SimpleHatch {
 impl Hatch for SimpleHatch {
     type Comm = ();
     type Error = crate::Error;
+    type Principal = User;
 
     fn comm(&self) -> &Self::Comm { &() }
 
@@ -42,24 +45,49 @@ impl Hatch for SimpleHatch {
         rocket::routes![login]
     }
 
+    fn login_uri(&self) -> Option<Origin<'static>> {
+        Some(rocket::uri!("/login?username="))
+    }
+
     async fn from(rocket: Rocket<Build>) -> HatchResult<SimpleHatch, Self::Error> {
         let name = SimpleHatch::name().replace(" ", "").to_lowercase();
         let config = match rocket.figment().extract_inner::<HatchConfig>(&format!("airlock.{}", name)) {
             Ok(config) => config,
             Err(e) => return Err((rocket, e.into())),
         };
-        Ok((rocket, SimpleHatch { valid_user: config.valid_user }))
+        Ok((rocket, SimpleHatch { valid_user: config.valid_user, roles: config.roles }))
+    }
+
+    async fn authenticate(&self, request: &Request<'_>) -> Option<User> {
+        let username = request.cookies()
+            .get_private("logged_in")?
+            .value()
+            .to_string();
+
+        // Here you could do something else with your hatch, like checking session lifetime or other stuff.
+        if self.is_session_expired(&username) {
+            return None;
+        }
+
+        Some(User { name: username, roles: self.roles.clone() })
     }
 }
 
 #[derive(Debug, Deserialize)]
 struct HatchConfig {
-    valid_user: String
+    valid_user: String,
+    #[serde(default)]
+    roles: Vec<String>,
 }
 
-#[rocket::get("/login?<username>")]
-pub fn login(airlock: Airlock<SimpleHatch>, username: String, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+#[rocket::get("/login?<username>&<return_to>")]
+pub fn login(airlock: Airlock<SimpleHatch>, username: String, return_to: Option<String>, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
     info_!("Someone tries to log in with username: {}", &username);
+    // Only return to paths of this app, so the login route can not redirect anywhere else.
+    let return_to = return_to.unwrap_or_else(|| "/".to_string());
+    if !ReturnTo::default().allows(&return_to) {
+        return Err(Status::BadRequest);
+    }
     match airlock.hatch.authenticate_username(&username) {
         true => {
             info_!("Authentication successfull!");
@@ -67,7 +95,7 @@ pub fn login(airlock: Airlock<SimpleHatch>, username: String, cookies: &CookieJa
                 Cookie::build(("logged_in", username))
                     .same_site(SameSite::Lax)
             );
-            Ok(Redirect::to("/"))
+            Ok(Redirect::to(return_to))
         }
         _ =>  Err(Status::Unauthorized),
     }
@@ -1,17 +1,31 @@
 use rocket::{get, info_, response::Redirect, routes};
-use rocket_airlock::Airlock;
+use rocket_airlock::{Airlock, Authenticated, Guarded, RedirectToLogin, bulkhead::HasRole, rbac::{HasPermission, Rbac}};
 use thiserror::Error;
-use user::User;
+use hatch::SimpleHatch;
 
 mod hatch;
 mod user;
 
 
 #[get("/")]
-fn index(user: User) -> String {
+fn index(user: Authenticated<SimpleHatch>) -> String {
     format!("Hello user: {}", user.name)
 }
 
+rocket_airlock::label!(Admin = "admin");
+
+#[get("/admin")]
+fn admin(user: Guarded<SimpleHatch, HasRole<Admin>>) -> String {
+    format!("Hello admin: {}", user.name)
+}
+
+rocket_airlock::label!(ReadArticles = "articles:read");
+
+#[get("/articles")]
+fn articles(user: Guarded<SimpleHatch, HasPermission<ReadArticles>, RedirectToLogin>) -> String {
+    format!("Here are your articles, {}", user.name)
+}
+
 #[get("/", rank = 2)]
 fn index_anon() -> Redirect {
     info_!("Anonymous user requested / -> redirecting to /login");
@@ -21,8 +35,9 @@ fn index_anon() -> Redirect {
 #[rocket::launch]
 fn rocket() -> _ {
     rocket::build()
-        .mount("/", routes![index, index_anon])
-        .attach(Airlock::<hatch::SimpleHatch>::fairing())
+        .mount("/", routes![index, index_anon, admin, articles])
+        .attach(Airlock::<SimpleHatch>::fairing())
+        .attach(Rbac::fairing())
 }
 
 #[derive(Debug, Error)]
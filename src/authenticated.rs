@@ -0,0 +1,299 @@
+use std::{marker::PhantomData, ops::Deref, sync::{Arc, Mutex}};
+use rocket::{
+    Ignite, Rocket, Sentinel,
+    http::{uri::{Absolute, Origin}, Cookie, Method, RawStr, Status},
+    outcome::try_outcome,
+    request::{FromRequest, Outcome, Request},
+};
+use crate::{Airlock, Hatch, Instance};
+
+
+/// Decides what happens with a request, if the hatch of an [`Authenticated`] guard denies entry.
+pub trait OnFailure: Send + Sync + 'static {
+    fn deny<S, H: Hatch + 'static, I: Instance>(request: &Request<'_>, airlock: &Airlock<H, I>) -> Outcome<S, ()>;
+}
+
+/// Forwards the request to the next matching route. This is the default behaviour.
+pub struct Forward;
+
+impl OnFailure for Forward {
+    fn deny<S, H: Hatch + 'static, I: Instance>(_request: &Request<'_>, _airlock: &Airlock<H, I>) -> Outcome<S, ()> {
+        Outcome::Forward(Status::Unauthorized)
+    }
+}
+
+/// Fails the request with `401 Unauthorized`, and with the challenge of the hatch in the
+/// `WWW-Authenticate` header, if it has one, see [`Hatch::challenge`].
+pub struct Reject;
+
+impl OnFailure for Reject {
+    fn deny<S, H: Hatch + 'static, I: Instance>(request: &Request<'_>, airlock: &Airlock<H, I>) -> Outcome<S, ()> {
+        if let Some(challenge) = airlock.hatch.challenge() {
+            request.local_cache(|| Challenge::<H>::of(challenge));
+        }
+        Outcome::Error((Status::Unauthorized, ()))
+    }
+}
+
+/// Redirects the request to the login route of the hatch, see [`Hatch::login_uri`], as it was mounted
+/// by the instance of the hatch. If the hatch has no login route, the request fails with `401 Unauthorized`
+/// like with [`Reject`]. The uri of a `GET` request is passed to the login route in the [`RETURN_TO`] query parameter, so the
+/// hatch can return there after the login.
+pub struct RedirectToLogin;
+
+impl OnFailure for RedirectToLogin {
+    fn deny<S, H: Hatch + 'static, I: Instance>(request: &Request<'_>, airlock: &Airlock<H, I>) -> Outcome<S, ()> {
+        if let Some(login_uri) = airlock.hatch.login_uri() {
+            let login_uri = match request.method() {
+                Method::Get => with_return_to(airlock.uri(login_uri), request.uri()),
+                _ => airlock.uri(login_uri),
+            };
+            request.local_cache(|| LoginRedirect::<H>::to(login_uri));
+        } else if let Some(challenge) = airlock.hatch.challenge() {
+            request.local_cache(|| Challenge::<H>::of(challenge));
+        }
+        Outcome::Error((Status::Unauthorized, ()))
+    }
+}
+
+/// The query parameter of the login route of a hatch, with the uri the login should return to.
+pub const RETURN_TO: &str = "return_to";
+
+/// Adds `target` to the query of `login_uri` as [`RETURN_TO`].
+fn with_return_to(login_uri: Origin<'static>, target: &Origin<'_>) -> Origin<'static> {
+    let target = target.to_string();
+    let return_to = format!("{}={}", RETURN_TO, RawStr::new(&target).percent_encode());
+    let uri = match login_uri.query() {
+        Some(query) if !query.is_empty() => format!("{}?{}&{}", login_uri.path(), query, return_to),
+        _ => format!("{}?{}", login_uri.path(), return_to),
+    };
+    Origin::parse_owned(uri).unwrap_or(login_uri)
+}
+
+/// The targets a login may return to, which prevents that the login route is abused as open redirect.
+/// Paths on the same origin, like `/orders?page=2`, are always allowed, absolute urls only if their
+/// origin is on the allowlist.
+#[derive(Debug, Clone, Default)]
+pub struct ReturnTo {
+    origins: Vec<String>,
+}
+
+impl ReturnTo {
+    /// Allows absolute urls on the `origins`, e.g. `https://portal.example.com`. Fails with the first
+    /// entry that is not an origin.
+    pub fn new<S: AsRef<str>>(origins: impl IntoIterator<Item = S>) -> Result<Self, String> {
+        let origins = origins.into_iter()
+            .map(|origin| Self::origin(origin.as_ref()).ok_or_else(|| origin.as_ref().to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(ReturnTo { origins })
+    }
+
+    /// The normalized origin of an allowlist entry, which may only have an empty path.
+    pub(crate) fn origin(origin: &str) -> Option<String> {
+        let url = Absolute::parse(origin).ok()?;
+        match url.path().as_str() {
+            "" | "/" if url.query().is_none() => Self::origin_of(&url),
+            _ => None,
+        }
+    }
+
+    /// The origin of an http(s) url, in lowercase.
+    fn origin_of(url: &Absolute<'_>) -> Option<String> {
+        let scheme = url.scheme().to_ascii_lowercase();
+        let authority = url.authority()?;
+        match scheme.as_str() {
+            "http" | "https" if authority.user_info().is_none() => {
+                Some(format!("{}://{}", scheme, authority.to_string().to_ascii_lowercase()))
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether a login may return to `target`.
+    pub fn allows(&self, target: &str) -> bool {
+        if target.chars().any(|c| c == '\\' || c.is_control()) {
+            return false;
+        }
+        if target.starts_with('/') {
+            // `//host` is relative to the scheme only, so it could lead to another origin.
+            return !target.starts_with("//") && Origin::parse(target).is_ok();
+        }
+        Absolute::parse(target).ok()
+            .and_then(|url| Self::origin_of(&url))
+            .is_some_and(|origin| self.origins.contains(&origin))
+    }
+}
+
+/// Marks a request that should be redirected to the login route of `H`, instead of answering
+/// with `401 Unauthorized`. The redirect itself is done by a response fairing of the airlock.
+pub(crate) struct LoginRedirect<H> {
+    pub(crate) uri: Option<Origin<'static>>,
+    _hatch: PhantomData<fn() -> H>,
+}
+
+impl<H> LoginRedirect<H> {
+    pub(crate) fn none() -> Self {
+        LoginRedirect { uri: None, _hatch: PhantomData }
+    }
+
+    fn to(uri: Origin<'static>) -> Self {
+        LoginRedirect { uri: Some(uri), _hatch: PhantomData }
+    }
+}
+
+/// The cookies `H` removed while it authenticated a request. Rocket discards the cookie changes of a request
+/// that ends with an error, so a response fairing of the airlock removes them again.
+pub(crate) struct RemovedCookies<H> {
+    pub(crate) names: Mutex<Vec<String>>,
+    _hatch: PhantomData<fn() -> H>,
+}
+
+impl<H> RemovedCookies<H> {
+    pub(crate) fn none() -> Self {
+        RemovedCookies { names: Mutex::new(Vec::new()), _hatch: PhantomData }
+    }
+}
+
+/// The private cookies `H` added while it authenticated a request, e.g. the tokens of a refresh. They are
+/// discarded with the other cookie changes of a request that ends with an error, so a response fairing of
+/// the airlock adds them again.
+pub(crate) struct AddedCookies<H> {
+    pub(crate) cookies: Mutex<Vec<Cookie<'static>>>,
+    _hatch: PhantomData<fn() -> H>,
+}
+
+impl<H> AddedCookies<H> {
+    pub(crate) fn none() -> Self {
+        AddedCookies { cookies: Mutex::new(Vec::new()), _hatch: PhantomData }
+    }
+}
+
+/// Marks a request whose `401 Unauthorized` response should carry the `WWW-Authenticate` challenge of `H`.
+/// The header is added by a response fairing of the airlock.
+pub(crate) struct Challenge<H> {
+    pub(crate) challenge: Option<String>,
+    _hatch: PhantomData<fn() -> H>,
+}
+
+impl<H> Challenge<H> {
+    pub(crate) fn none() -> Self {
+        Challenge { challenge: None, _hatch: PhantomData }
+    }
+
+    fn of(challenge: String) -> Self {
+        Challenge { challenge: Some(challenge), _hatch: PhantomData }
+    }
+}
+
+/// Request guard that runs the security checks of the hatch `H` and only succeeds if they pass.
+/// It dereferences to the authenticated [`Principal`](Hatch::Principal). What happens if the
+/// hatch denies entry is decided by `F`, which is one of [`Forward`], [`Reject`] or [`RedirectToLogin`].
+/// `I` selects a named [`Instance`] of the hatch, by default the primary instance is used.
+/// The principal is cached, so using several of these guards in one handler authenticates the request only once.
+///
+/// ```rust,ignore
+/// #[get("/")]
+/// fn index(user: Authenticated<SimpleHatch, RedirectToLogin>) -> String {
+///     format!("Hello user: {}", user.name)
+/// }
+/// ```
+pub struct Authenticated<H: Hatch, F: OnFailure = Forward, I: Instance = ()> {
+    principal: Arc<H::Principal>,
+    _failure: PhantomData<fn() -> (F, I)>,
+}
+
+impl<H: Hatch, F: OnFailure, I: Instance> Authenticated<H, F, I> {
+    /// Consumes the guard and returns the authenticated principal.
+    pub fn into_inner(self) -> Arc<H::Principal> {
+        self.principal
+    }
+}
+
+impl<H: Hatch, F: OnFailure, I: Instance> Deref for Authenticated<H, F, I> {
+    type Target = H::Principal;
+
+    fn deref(&self) -> &Self::Target {
+        &self.principal
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, H: Hatch + 'static, F: OnFailure, I: Instance> FromRequest<'r> for Authenticated<H, F, I> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let airlock = try_outcome!(request.guard::<Airlock<H, I>>().await);
+        match Airlock::<H, I>::authenticate(request).await {
+            Some(principal) => Outcome::Success(Authenticated { principal, _failure: PhantomData }),
+            None => F::deny(request, &airlock),
+        }
+    }
+}
+
+impl<H: Hatch + 'static, F: OnFailure, I: Instance> Sentinel for Authenticated<H, F, I> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        Airlock::<H, I>::abort(rocket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn return_to() -> ReturnTo {
+        ReturnTo::new(["https://Allowed.Example.com"]).unwrap()
+    }
+
+    #[test]
+    fn paths_are_allowed() {
+        let return_to = return_to();
+        assert!(return_to.allows("/"));
+        assert!(return_to.allows("/orders?page=2&sort=date"));
+    }
+
+    #[test]
+    fn scheme_relative_urls_are_rejected() {
+        let return_to = return_to();
+        assert!(!return_to.allows("//evil.com"));
+        assert!(!return_to.allows("//allowed.example.com"));
+        assert!(!return_to.allows("/\\evil.com"));
+        assert!(!return_to.allows("\\\\evil.com"));
+    }
+
+    #[test]
+    fn control_characters_are_rejected() {
+        let return_to = return_to();
+        assert!(!return_to.allows("/orders\r\nLocation: https://evil.com"));
+        assert!(!return_to.allows("/\t/evil.com"));
+        assert!(!return_to.allows("https://allowed.example.com/\0"));
+    }
+
+    #[test]
+    fn origins_on_the_allowlist_are_allowed() {
+        let return_to = return_to();
+        assert!(return_to.allows("https://allowed.example.com"));
+        assert!(return_to.allows("https://allowed.example.com/orders?page=2"));
+        assert!(return_to.allows("HTTPS://ALLOWED.example.COM/orders"));
+    }
+
+    #[test]
+    fn other_origins_are_rejected() {
+        let return_to = return_to();
+        assert!(!return_to.allows("https://evil.com"));
+        assert!(!return_to.allows("https://allowed.example.com.evil.com/"));
+        assert!(!return_to.allows("https://allowed.example.com@evil.com/"));
+        assert!(!return_to.allows("https://allowed@evil.com"));
+        assert!(!return_to.allows("http://allowed.example.com"));
+        assert!(!return_to.allows("https://allowed.example.com:8443"));
+        assert!(!return_to.allows("javascript:alert(1)"));
+        assert!(!return_to.allows("allowed.example.com/orders"));
+    }
+
+    #[test]
+    fn allowlist_entries_are_origins() {
+        assert_eq!(ReturnTo::origin("HTTPS://Allowed.Example.com/").as_deref(), Some("https://allowed.example.com"));
+        assert!(ReturnTo::new(["https://allowed.example.com/portal"]).is_err());
+        assert!(ReturnTo::new(["https://user@allowed.example.com"]).is_err());
+        assert!(ReturnTo::new(["ftp://allowed.example.com"]).is_err());
+    }
+}
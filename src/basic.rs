@@ -0,0 +1,439 @@
+//! HTTP Basic authentication, e.g. for internal tools. The [`BasicHatch`] authenticates requests with the
+//! username and password of the `Authorization: Basic` header, as specified by
+//! [RFC 7617](https://www.rfc-editor.org/rfc/rfc7617), and checks them with the [`Verifier`] that is its
+//! communicator. It is configured in the `airlock.basic` table of the rocket config, e.g.:
+//!
+//! ```toml
+//! [default.airlock.basic]
+//! # Optional, the protection space that browsers show in their login dialog.
+//! realm = "Internal tools"
+//! # The Apache htpasswd file of the `HtpasswdFile` verifier.
+//! htpasswd = "users.htpasswd"
+//!
+//! # The users of the `UserList` verifier, with the hashes of their passwords.
+//! [default.airlock.basic.users]
+//! alice = "$2y$10$..."
+//! bob = "$argon2id$v=19$m=19456,t=2,p=1$..."
+//! ```
+//!
+//! Passwords are only stored as bcrypt (`$2a$`, `$2b$`, `$2y$`) or argon2 (`$argon2id$`, `$argon2i$`,
+//! `$argon2d$`) hashes, e.g. as created by `htpasswd -B`. Other hashes are rejected at ignite. Usernames
+//! are compared in constant time, and the password of an unknown user is checked against a hash as well,
+//! so the response time does not tell whether a user exists. Passwords are never logged.
+//!
+//! ```rust,ignore
+//! #[get("/tools")]
+//! fn tools(user: Authenticated<BasicHatch<HtpasswdFile>, Reject>) -> String {
+//!     format!("Hello user: {}", user.username)
+//! }
+//!
+//! rocket::build().attach(Airlock::<BasicHatch<HtpasswdFile>>::fairing())
+//! ```
+//!
+//! The hatch has no routes. Use it with [`Reject`](crate::Reject), so requests without valid credentials
+//! are answered with `401 Unauthorized` and a `WWW-Authenticate: Basic` challenge, which makes browsers
+//! ask for a username and password.
+//!
+//! Only available with the `basic` feature.
+
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}, sync::Arc};
+use argon2::{password_hash::{PasswordHash, PasswordVerifier}, Argon2};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::{
+    Build, info_, Request, Rocket, warn_,
+    figment::{self, Figment},
+    tokio::{fs, task},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+use yansi::Paint;
+use crate::{Communicator, Hatch, Result as HatchResult, registry};
+
+
+/// Errors of the [`BasicHatch`] and its verifiers.
+#[derive(Debug)]
+pub enum BasicError {
+    /// The config in `airlock.basic` is missing or invalid.
+    Config(Box<figment::Error>),
+    /// The htpasswd file could not be read or contains an invalid entry.
+    Htpasswd(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for BasicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BasicError::Config(e) => write!(f, "invalid Basic authentication config: {}", e),
+            BasicError::Htpasswd(e) => write!(f, "loading the htpasswd file failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BasicError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BasicError::Config(e) => Some(&**e),
+            BasicError::Htpasswd(e) => Some(&**e),
+        }
+    }
+}
+
+impl From<figment::Error> for BasicError {
+    fn from(e: figment::Error) -> Self {
+        BasicError::Config(Box::new(e))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BasicConfig {
+    #[serde(default = "default_realm")]
+    realm: String,
+}
+
+fn default_realm() -> String {
+    "airlock".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct HtpasswdConfig {
+    htpasswd: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsersConfig {
+    users: HashMap<String, String>,
+}
+
+/// Extracts the config table of the hatch as `T`.
+#[allow(clippy::result_large_err)]
+fn config<T: DeserializeOwned>(figment: &Figment) -> Result<T, figment::Error> {
+    figment.extract_inner(&format!("airlock.{}", config_name()))
+}
+
+/// The name of the config table of the hatch, which is the same for every verifier.
+fn config_name() -> String {
+    registry::config_name::<BasicHatch<UserList>>()
+}
+
+/// Checks the credentials of the [`BasicHatch`]. Implement it for a [`Communicator`] to check them
+/// somewhere else than in [`HtpasswdFile`] or [`UserList`], e.g. in a database.
+#[rocket::async_trait]
+pub trait Verifier: Communicator + 'static {
+    /// Whether `password` is the password of the user `username`.
+    async fn verify(&self, username: &str, password: &str) -> bool;
+}
+
+/// A password hash, which is verified in constant time.
+enum Hash {
+    Bcrypt(String),
+    Argon2(String),
+}
+
+impl Hash {
+    /// Parses a bcrypt or argon2 hash, or returns `None` for any other hash.
+    fn parse(hash: &str) -> Option<Self> {
+        if ["$2a$", "$2b$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix)) {
+            return hash.parse::<bcrypt::HashParts>().ok().map(|_| Hash::Bcrypt(hash.to_string()));
+        }
+        let parsed = PasswordHash::new(hash).ok()?;
+        match parsed.algorithm.as_str() {
+            "argon2id" | "argon2i" | "argon2d" => Some(Hash::Argon2(hash.to_string())),
+            _ => None,
+        }
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Hash::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Hash::Argon2(hash) => PasswordHash::new(hash)
+                .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok()),
+        }
+    }
+}
+
+/// Users with the hashes of their passwords.
+struct Passwords(Vec<(String, Hash)>);
+
+impl Passwords {
+    /// Checks the credentials without revealing through the time it takes whether the user exists. An
+    /// unknown user is checked against the hash of the first user.
+    fn verify(&self, username: &str, password: &str) -> bool {
+        let mut index = 0u64;
+        let mut found = Choice::from(0);
+        for (i, (name, _)) in self.0.iter().enumerate() {
+            let matches = name.as_bytes().ct_eq(username.as_bytes());
+            index.conditional_assign(&(i as u64), matches);
+            found |= matches;
+        }
+
+        let Some((_, hash)) = self.0.get(index as usize) else {
+            return false;
+        };
+        let verified = Choice::from(u8::from(hash.verify(password)));
+        bool::from(found & verified)
+    }
+
+    /// Verifies the credentials on a blocking thread, as password hashes are deliberately slow.
+    async fn verify_blocking(self: &Arc<Self>, username: &str, password: &str) -> bool {
+        let (passwords, username, password) = (Arc::clone(self), username.to_string(), password.to_string());
+        task::spawn_blocking(move || passwords.verify(&username, &password)).await
+            .unwrap_or(false)
+    }
+}
+
+/// A [`Verifier`] with the users of an Apache htpasswd file, whose path is `htpasswd` in the config.
+/// Every line of the file is a `username:hash` pair, empty lines and lines starting with `#` are skipped.
+pub struct HtpasswdFile {
+    passwords: Arc<Passwords>,
+}
+
+impl HtpasswdFile {
+    /// Parses the `content` of an htpasswd file.
+    pub fn parse(content: &str) -> Result<Self, BasicError> {
+        let users = content.lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| {
+                let (username, hash) = line.split_once(':')
+                    .ok_or_else(|| BasicError::Htpasswd(format!("line {} is not a `username:hash` pair", number).into()))?;
+                let hash = Hash::parse(hash)
+                    .ok_or_else(|| BasicError::Htpasswd(format!("the password of `{}` in line {} is neither a bcrypt nor an argon2 hash", username, number).into()))?;
+                Ok((username.to_string(), hash))
+            })
+            .collect::<Result<_, BasicError>>()?;
+        Ok(HtpasswdFile { passwords: Arc::new(Passwords(users)) })
+    }
+
+    /// Reads and parses the htpasswd file at `path`.
+    pub async fn load(path: &Path) -> Result<Self, BasicError> {
+        let content = fs::read_to_string(path).await
+            .map_err(|e| BasicError::Htpasswd(Box::new(e)))?;
+        HtpasswdFile::parse(&content)
+    }
+}
+
+#[rocket::async_trait]
+impl Communicator for HtpasswdFile {
+    type Error = BasicError;
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match config::<HtpasswdConfig>(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        info_!("Loading users from: {}", Paint::new(config.htpasswd.display()).underline());
+        match HtpasswdFile::load(&config.htpasswd).await {
+            Ok(file) => {
+                info_!("Loaded {} users", file.passwords.0.len());
+                Ok((rocket, file))
+            },
+            Err(e) => Err((rocket, e)),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Verifier for HtpasswdFile {
+    async fn verify(&self, username: &str, password: &str) -> bool {
+        self.passwords.verify_blocking(username, password).await
+    }
+}
+
+/// A [`Verifier`] with the users of the `users` table in the config, which maps usernames to the hashes
+/// of their passwords.
+pub struct UserList {
+    passwords: Arc<Passwords>,
+}
+
+impl UserList {
+    /// Creates the list from usernames and the hashes of their passwords. Fails with the first user
+    /// whose password is neither a bcrypt nor an argon2 hash.
+    pub fn new<S: Into<String>>(users: impl IntoIterator<Item = (S, S)>) -> Result<Self, String> {
+        let users = users.into_iter()
+            .map(|(username, hash)| {
+                let username = username.into();
+                match Hash::parse(&hash.into()) {
+                    Some(hash) => Ok((username, hash)),
+                    None => Err(username),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(UserList { passwords: Arc::new(Passwords(users)) })
+    }
+}
+
+#[rocket::async_trait]
+impl Communicator for UserList {
+    type Error = BasicError;
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match config::<UsersConfig>(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        match UserList::new(config.users) {
+            Ok(list) => Ok((rocket, list)),
+            Err(username) => {
+                let e = format!("the password of `{}` is neither a bcrypt nor an argon2 hash", username);
+                let e = figment::Error::from(e).with_path(&format!("airlock.{}.users", config_name()));
+                Err((rocket, e.into()))
+            },
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Verifier for UserList {
+    async fn verify(&self, username: &str, password: &str) -> bool {
+        self.passwords.verify_blocking(username, password).await
+    }
+}
+
+/// The user that logged in with HTTP Basic authentication.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BasicUser {
+    pub username: String,
+}
+
+/// Hatch that authenticates requests with HTTP Basic authentication. See the [module docs](self).
+pub struct BasicHatch<V: Verifier> {
+    verifier: Option<V>,
+    realm: String,
+}
+
+/// The username and password of the `Authorization: Basic` header of `request`.
+fn credentials(request: &Request<'_>) -> Option<(String, String)> {
+    let header = request.headers().get_one("Authorization")?;
+    let (scheme, credentials) = header.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+
+    let credentials = STANDARD.decode(credentials.trim()).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (username, password) = credentials.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+#[rocket::async_trait]
+impl<V: Verifier> Hatch for BasicHatch<V> {
+    type Comm = V;
+    type Error = BasicError;
+    type Principal = BasicUser;
+
+    fn comm(&self) -> &V {
+        self.verifier.as_ref().expect("Communicator should have been connected")
+    }
+
+    fn connect_comm(&mut self, comm: Self::Comm) {
+        self.verifier = Some(comm);
+    }
+
+    fn name() -> &'static str {
+        "Basic"
+    }
+
+    fn challenge(&self) -> Option<String> {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        Some(format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm))
+    }
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match config::<BasicConfig>(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        info_!("Realm: {}", config.realm);
+        Ok((rocket, BasicHatch { verifier: None, realm: config.realm }))
+    }
+
+    async fn authenticate(&self, request: &Request<'_>) -> Option<BasicUser> {
+        let (username, password) = credentials(request)?;
+        match self.comm().verify(&username, &password).await {
+            true => Some(BasicUser { username }),
+            false => {
+                warn_!("Rejecting invalid Basic credentials");
+                None
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+    use super::*;
+
+    fn bcrypt(password: &str) -> String {
+        bcrypt::hash(password, 4).unwrap()
+    }
+
+    fn argon2(password: &str) -> String {
+        let salt = SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").unwrap();
+        // Cheap parameters, the verification takes them from the hash.
+        let params = argon2::Params::new(256, 1, 1, None).unwrap();
+        Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password(password.as_bytes(), &salt).unwrap().to_string()
+    }
+
+    fn error(content: &str) -> String {
+        match HtpasswdFile::parse(content) {
+            Ok(_) => panic!("`{}` is not a valid htpasswd file", content),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_bcrypt_and_argon2_entries() {
+        let content = format!("# Users\r\n\nalice:{}\r\n  bob:{}  \n", bcrypt("alice's"), argon2("bob's"));
+        let file = HtpasswdFile::parse(&content).unwrap();
+        let names: Vec<_> = file.passwords.0.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert!(matches!(file.passwords.0[0].1, Hash::Bcrypt(_)));
+        assert!(matches!(file.passwords.0[1].1, Hash::Argon2(_)));
+    }
+
+    #[test]
+    fn rejects_other_hashes() {
+        for entry in ["alice:secret", "alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=", "alice:$apr1$salt$hash", "alice:"] {
+            assert!(error(entry).contains("the password of `alice` in line 1 is neither"), "{}", entry);
+        }
+        let scrypt = "alice:$scrypt$ln=16,r=8,p=1$c2FsdA$aGFzaA";
+        assert!(error(scrypt).contains("neither a bcrypt nor an argon2 hash"));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let content = format!("alice:{}\nbob", bcrypt("alice's"));
+        assert!(error(&content).contains("line 2 is not a `username:hash` pair"));
+        assert!(error("$2y$04$truncated").contains("line 1"));
+    }
+
+    #[test]
+    fn verifies_passwords() {
+        let file = HtpasswdFile::parse(&format!("alice:{}\nbob:{}", bcrypt("alice's"), argon2("bob's"))).unwrap();
+        assert!(file.passwords.verify("alice", "alice's"));
+        assert!(file.passwords.verify("bob", "bob's"));
+        assert!(!file.passwords.verify("alice", "bob's"));
+        assert!(!file.passwords.verify("bob", "alice's"));
+        assert!(!file.passwords.verify("Alice", "alice's"));
+        assert!(!file.passwords.verify("alice", ""));
+    }
+
+    #[test]
+    fn unknown_users_are_rejected() {
+        let file = HtpasswdFile::parse(&format!("alice:{}", bcrypt("alice's"))).unwrap();
+        assert!(!file.passwords.verify("mallory", "alice's"));
+        assert!(!file.passwords.verify("", "alice's"));
+        assert!(!HtpasswdFile::parse("# No users").unwrap().passwords.verify("alice", "alice's"));
+    }
+
+    #[test]
+    fn user_list_rejects_other_hashes() {
+        assert!(UserList::new([("alice", bcrypt("alice's").as_str())]).is_ok());
+        assert_eq!(UserList::new([("alice", "secret")]).err().as_deref(), Some("alice"));
+    }
+}
@@ -0,0 +1,412 @@
+//! A bulkhead divides a rocket into compartments. Passing the airlock grants entry into the rocket,
+//! but only the bulkheads decide which compartments a principal may enter.
+//!
+//! A [`Bulkhead`] is a policy, which is evaluated against the principal of a hatch and the request.
+//! Policies are zero-sized types, so a route states its requirements right in its signature with
+//! the [`Guarded`] request guard, and they can be composed with [`And`], [`Or`] and [`Not`].
+//!
+//! ```rust,ignore
+//! rocket_airlock::label!(Admin = "admin");
+//! rocket_airlock::label!(Editor = "editor");
+//!
+//! #[get("/articles/edit")]
+//! fn edit(user: Guarded<SimpleHatch, Or<HasRole<Admin>, HasRole<Editor>>>) -> String {
+//!     format!("Hello editor: {}", user.name)
+//! }
+//! ```
+
+use std::{any::type_name, borrow::Cow, fmt, marker::PhantomData, ops::Deref, sync::Arc};
+use rocket::{
+    Ignite, Rocket, Sentinel,
+    http::Status,
+    info_,
+    outcome::try_outcome,
+    request::{FromRequest, Outcome, Request},
+};
+use crate::{Airlock, Forward, Hatch, Instance, OnFailure};
+
+
+/// A policy that decides whether a principal may pass. It is evaluated against the principal
+/// of a hatch and the request that principal is making.
+#[rocket::async_trait]
+pub trait Bulkhead<P: Send + Sync>: Send + Sync + 'static {
+    /// Inspects the principal and the request. Returns a [`Breach`] with the reason, if the
+    /// principal is not allowed to pass, or a [`Breach::failure`] if the policy could not be evaluated.
+    async fn inspect(principal: &P, request: &Request<'_>) -> Result<(), Breach>;
+
+    /// Short description of the policy, which is used in the reasons of breaches.
+    fn describe() -> String {
+        type_name::<Self>().to_string()
+    }
+
+    /// Works like [`Sentinel::abort`] and is queried for every policy used by a [`Guarded`] route.
+    /// Returns `true` to abort the launch, e.g. if the policy needs state that was not installed.
+    #[allow(unused_variables)]
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        false
+    }
+}
+
+/// The reason, why a bulkhead denied passage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breach {
+    pub reason: Cow<'static, str>,
+    failure: bool,
+}
+
+impl Breach {
+    /// The principal is not allowed to pass.
+    pub fn new(reason: impl Into<Cow<'static, str>>) -> Self {
+        Breach { reason: reason.into(), failure: false }
+    }
+
+    /// The policy could not decide, e.g. because state it needs was not installed. Unlike a denial,
+    /// a failure is never turned into a pass by [`Not`], and it is answered with `500 Internal Server Error`.
+    pub fn failure(reason: impl Into<Cow<'static, str>>) -> Self {
+        Breach { reason: reason.into(), failure: true }
+    }
+
+    /// Whether the policy could not decide, instead of denying passage.
+    pub fn is_failure(&self) -> bool {
+        self.failure
+    }
+
+    /// The status a request is rejected with because of this breach.
+    pub fn status(&self) -> Status {
+        match self.failure {
+            true => Status::InternalServerError,
+            false => Status::Forbidden,
+        }
+    }
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+impl std::error::Error for Breach {}
+
+/// A name on type level, e.g. of a role or a scope. Create one with the [`label!`](crate::label) macro.
+pub trait Label: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// Creates a zero-sized type that implements [`Label`], for use in policies such as [`HasRole`].
+///
+/// ```rust,ignore
+/// rocket_airlock::label!(pub Admin = "admin");
+/// ```
+#[macro_export]
+macro_rules! label {
+    ($(#[$attr:meta])* $vis:vis $name:ident = $value:literal) => {
+        $(#[$attr])*
+        $vis struct $name;
+
+        impl $crate::bulkhead::Label for $name {
+            const NAME: &'static str = $value;
+        }
+    };
+}
+
+/// A principal that has roles.
+pub trait Roles {
+    fn roles(&self) -> &[String];
+
+    fn has_role(&self, role: &str) -> bool {
+        self.roles().iter().any(|r| r == role)
+    }
+}
+
+/// A principal that was granted scopes, as is common for OAuth 2.0 access tokens.
+pub trait Scopes {
+    fn scopes(&self) -> &[String];
+
+    fn has_scope(&self, scope: &str) -> bool {
+        self.scopes().iter().any(|s| s == scope)
+    }
+}
+
+/// Passes if the principal has the role `R`.
+pub struct HasRole<R: Label>(PhantomData<fn() -> R>);
+
+#[rocket::async_trait]
+impl<P: Roles + Send + Sync, R: Label> Bulkhead<P> for HasRole<R> {
+    async fn inspect(principal: &P, _request: &Request<'_>) -> Result<(), Breach> {
+        match principal.has_role(R::NAME) {
+            true => Ok(()),
+            false => Err(Breach::new(format!("missing {}", <Self as Bulkhead<P>>::describe()))),
+        }
+    }
+
+    fn describe() -> String {
+        format!("role `{}`", R::NAME)
+    }
+}
+
+/// Passes if the principal was granted the scope `S`.
+pub struct HasScope<S: Label>(PhantomData<fn() -> S>);
+
+#[rocket::async_trait]
+impl<P: Scopes + Send + Sync, S: Label> Bulkhead<P> for HasScope<S> {
+    async fn inspect(principal: &P, _request: &Request<'_>) -> Result<(), Breach> {
+        match principal.has_scope(S::NAME) {
+            true => Ok(()),
+            false => Err(Breach::new(format!("missing {}", <Self as Bulkhead<P>>::describe()))),
+        }
+    }
+
+    fn describe() -> String {
+        format!("scope `{}`", S::NAME)
+    }
+}
+
+/// Passes if both `A` and `B` pass.
+pub struct And<A, B>(PhantomData<fn() -> (A, B)>);
+
+#[rocket::async_trait]
+impl<P: Send + Sync, A: Bulkhead<P>, B: Bulkhead<P>> Bulkhead<P> for And<A, B> {
+    async fn inspect(principal: &P, request: &Request<'_>) -> Result<(), Breach> {
+        A::inspect(principal, request).await?;
+        B::inspect(principal, request).await
+    }
+
+    fn describe() -> String {
+        format!("({} and {})", A::describe(), B::describe())
+    }
+
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        A::abort(rocket) | B::abort(rocket)
+    }
+}
+
+/// Passes if either `A` or `B` passes.
+pub struct Or<A, B>(PhantomData<fn() -> (A, B)>);
+
+#[rocket::async_trait]
+impl<P: Send + Sync, A: Bulkhead<P>, B: Bulkhead<P>> Bulkhead<P> for Or<A, B> {
+    async fn inspect(principal: &P, request: &Request<'_>) -> Result<(), Breach> {
+        match A::inspect(principal, request).await {
+            Ok(()) => Ok(()),
+            Err(a) if a.is_failure() => Err(a),
+            Err(a) => B::inspect(principal, request).await
+                .map_err(|b| Breach { reason: format!("{} and {}", a, b).into(), failure: b.failure }),
+        }
+    }
+
+    fn describe() -> String {
+        format!("({} or {})", A::describe(), B::describe())
+    }
+
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        A::abort(rocket) | B::abort(rocket)
+    }
+}
+
+/// Passes if `A` denies passage. If `A` fails to decide, `Not` fails as well.
+pub struct Not<A>(PhantomData<fn() -> A>);
+
+#[rocket::async_trait]
+impl<P: Send + Sync, A: Bulkhead<P>> Bulkhead<P> for Not<A> {
+    async fn inspect(principal: &P, request: &Request<'_>) -> Result<(), Breach> {
+        match A::inspect(principal, request).await {
+            Ok(()) => Err(Breach::new(format!("must not have {}", A::describe()))),
+            Err(breach) if breach.is_failure() => Err(breach),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn describe() -> String {
+        format!("not {}", A::describe())
+    }
+
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        A::abort(rocket)
+    }
+}
+
+/// Request guard that authenticates a request with the hatch `H` and then lets the policy `P`
+/// inspect the principal. If the policy denies passage, the request fails with `403 Forbidden`
+/// and the [`Breach`] as error, or with `500 Internal Server Error` if the policy failed to decide. If authentication fails, `F` decides what happens, exactly like
+/// for [`Authenticated`](crate::Authenticated), and `I` selects a named [`Instance`] of the hatch.
+/// Dereferences to the principal.
+pub struct Guarded<H: Hatch, P, F: OnFailure = Forward, I: Instance = ()> {
+    principal: Arc<H::Principal>,
+    _policy: PhantomData<fn() -> P>,
+    _failure: PhantomData<fn() -> (F, I)>,
+}
+
+impl<H: Hatch, P, F: OnFailure, I: Instance> Guarded<H, P, F, I> {
+    /// Consumes the guard and returns the principal that passed the bulkhead.
+    pub fn into_inner(self) -> Arc<H::Principal> {
+        self.principal
+    }
+}
+
+impl<H: Hatch, P, F: OnFailure, I: Instance> Deref for Guarded<H, P, F, I> {
+    type Target = H::Principal;
+
+    fn deref(&self) -> &Self::Target {
+        &self.principal
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, H, P, F, I> FromRequest<'r> for Guarded<H, P, F, I>
+where
+    H: Hatch + 'static,
+    P: Bulkhead<H::Principal>,
+    F: OnFailure,
+    I: Instance,
+{
+    type Error = Breach;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let airlock = try_outcome!(request.guard::<Airlock<H, I>>().await
+            .map_error(|(status, ())| (status, Breach::new(format!("hatch `{}` is not installed", Airlock::<H, I>::name())))));
+        let principal = match Airlock::<H, I>::authenticate(request).await {
+            Some(principal) => principal,
+            None => return F::deny(request, &airlock)
+                .map_error(|(status, ())| (status, Breach::new("not authenticated"))),
+        };
+
+        match P::inspect(&principal, request).await {
+            Ok(()) => Outcome::Success(Guarded { principal, _policy: PhantomData, _failure: PhantomData }),
+            Err(breach) => {
+                info_!("Bulkhead {} denied passage: {}", P::describe(), breach);
+                Outcome::Error((breach.status(), breach))
+            },
+        }
+    }
+}
+
+impl<H: Hatch + 'static, P: Bulkhead<H::Principal>, F: OnFailure, I: Instance> Sentinel for Guarded<H, P, F, I> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        <Airlock<H, I> as Sentinel>::abort(rocket) | <P as Bulkhead<H::Principal>>::abort(rocket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use rocket::local::asynchronous::Client;
+    use super::*;
+
+    label!(Admin = "admin");
+    label!(Write = "articles:write");
+
+    #[derive(Default)]
+    struct Principal {
+        roles: Vec<String>,
+        scopes: Vec<String>,
+        inspections: AtomicUsize,
+    }
+
+    impl Roles for Principal {
+        fn roles(&self) -> &[String] {
+            &self.roles
+        }
+    }
+
+    impl Scopes for Principal {
+        fn scopes(&self) -> &[String] {
+            &self.scopes
+        }
+    }
+
+    struct Pass;
+
+    #[rocket::async_trait]
+    impl Bulkhead<Principal> for Pass {
+        async fn inspect(principal: &Principal, _request: &Request<'_>) -> Result<(), Breach> {
+            principal.inspections.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct Deny;
+
+    #[rocket::async_trait]
+    impl Bulkhead<Principal> for Deny {
+        async fn inspect(principal: &Principal, _request: &Request<'_>) -> Result<(), Breach> {
+            principal.inspections.fetch_add(1, Ordering::SeqCst);
+            Err(Breach::new("denied"))
+        }
+    }
+
+    struct Fail;
+
+    #[rocket::async_trait]
+    impl Bulkhead<Principal> for Fail {
+        async fn inspect(principal: &Principal, _request: &Request<'_>) -> Result<(), Breach> {
+            principal.inspections.fetch_add(1, Ordering::SeqCst);
+            Err(Breach::failure("failed"))
+        }
+    }
+
+    /// Inspects a fresh principal with `B`. Returns the status of the breach and how many policies inspected it.
+    async fn inspect<B: Bulkhead<Principal>>(principal: Principal) -> (Option<Status>, usize) {
+        let client = Client::untracked(rocket::build()).await.unwrap();
+        let request = client.get("/");
+        let result = B::inspect(&principal, request.inner()).await;
+        (result.err().map(|breach| breach.status()), principal.inspections.load(Ordering::SeqCst))
+    }
+
+    async fn status<B: Bulkhead<Principal>>() -> Option<Status> {
+        inspect::<B>(Principal::default()).await.0
+    }
+
+    #[rocket::async_test]
+    async fn and_short_circuits() {
+        assert_eq!(inspect::<And<Deny, Pass>>(Principal::default()).await, (Some(Status::Forbidden), 1));
+        assert_eq!(inspect::<And<Fail, Pass>>(Principal::default()).await, (Some(Status::InternalServerError), 1));
+        assert_eq!(inspect::<And<Pass, Deny>>(Principal::default()).await, (Some(Status::Forbidden), 2));
+        assert_eq!(inspect::<And<Pass, Pass>>(Principal::default()).await, (None, 2));
+    }
+
+    #[rocket::async_test]
+    async fn or_short_circuits() {
+        assert_eq!(inspect::<Or<Pass, Fail>>(Principal::default()).await, (None, 1));
+        assert_eq!(inspect::<Or<Deny, Pass>>(Principal::default()).await, (None, 2));
+        assert_eq!(status::<Or<Deny, Deny>>().await, Some(Status::Forbidden));
+    }
+
+    #[rocket::async_test]
+    async fn or_prefers_failures_to_denials() {
+        assert_eq!(status::<Or<Deny, Fail>>().await, Some(Status::InternalServerError));
+        assert_eq!(inspect::<Or<Fail, Deny>>(Principal::default()).await, (Some(Status::InternalServerError), 1));
+        assert_eq!(inspect::<Or<Fail, Pass>>(Principal::default()).await, (Some(Status::InternalServerError), 1));
+    }
+
+    #[rocket::async_test]
+    async fn not_only_negates_denials() {
+        assert_eq!(status::<Not<Deny>>().await, None);
+        assert_eq!(status::<Not<Pass>>().await, Some(Status::Forbidden));
+        assert_eq!(status::<Not<Fail>>().await, Some(Status::InternalServerError));
+        assert_eq!(status::<Not<Not<Fail>>>().await, Some(Status::InternalServerError));
+        assert_eq!(status::<Not<Or<Deny, Fail>>>().await, Some(Status::InternalServerError));
+    }
+
+    #[rocket::async_test]
+    async fn roles_and_scopes_need_to_be_granted() {
+        let admin = || Principal { roles: vec!["admin".into()], scopes: vec!["articles:write".into()], ..Default::default() };
+        assert_eq!(inspect::<HasRole<Admin>>(admin()).await.0, None);
+        assert_eq!(inspect::<HasScope<Write>>(admin()).await.0, None);
+        assert_eq!(status::<HasRole<Admin>>().await, Some(Status::Forbidden));
+        assert_eq!(status::<HasScope<Write>>().await, Some(Status::Forbidden));
+
+        let swapped = || Principal { roles: vec!["articles:write".into()], scopes: vec!["admin".into()], ..Default::default() };
+        assert_eq!(inspect::<HasRole<Write>>(swapped()).await.0, None);
+        assert_eq!(inspect::<HasRole<Admin>>(swapped()).await.0, Some(Status::Forbidden));
+        assert_eq!(inspect::<HasScope<Write>>(swapped()).await.0, Some(Status::Forbidden));
+    }
+
+    #[test]
+    fn breaches_describe_the_policy() {
+        assert_eq!(<And<HasRole<Admin>, Not<HasScope<Write>>> as Bulkhead<Principal>>::describe(),
+            "(role `admin` and not scope `articles:write`)");
+        assert_eq!(<Or<HasRole<Admin>, HasScope<Write>> as Bulkhead<Principal>>::describe(),
+            "(role `admin` or scope `articles:write`)");
+    }
+}
@@ -0,0 +1,420 @@
+//! Declarative route protection. Checkpoints enforce the rules of the `airlock.routes` table for
+//! every mounted route, without touching the signatures of their handlers, e.g.:
+//!
+//! ```toml
+//! [[default.airlock.routes]]
+//! path = "/admin/**"
+//! methods = ["GET", "POST"]
+//! hatch = "simple"
+//! policy = "admin"
+//! ```
+//!
+//! A rule matches requests whose path matches the glob `path` and whose method is one of `methods`,
+//! or any method if `methods` is missing. Paths are matched percent-decoded and without empty
+//! segments, the way rocket routes them, so `//%61dmin` is checked like `/admin`. `hatch` is the name of an installed hatch, or of one of its
+//! named [`Instance`](crate::Instance)s. The first matching rule wins. Requests that the hatch does
+//! not authenticate are rejected with `401 Unauthorized`. If the rule names a `policy`, the principal
+//! also needs to pass it, otherwise the request is rejected with `403 Forbidden`, or with
+//! `500 Internal Server Error` if the policy could not decide, see [`Breach::failure`]. Policies are
+//! [`Bulkhead`]s that are registered under a name in code:
+//!
+//! ```rust,ignore
+//! rocket::build()
+//!     .attach(Airlock::<SimpleHatch>::fairing())
+//!     .attach(Checkpoints::fairing().policy::<SimpleHatch, HasRole<Admin>>("admin"))
+//! ```
+//!
+//! Rules that reference an unknown hatch or policy fail ignition.
+//!
+//! # Lockdown
+//!
+//! By default, routes that are not covered by a rule are left alone. In lockdown, everything that
+//! is not explicitly public is denied instead. The routes mounted by hatches, such as their login
+//! routes, are always public. Further public paths are configured as globs:
+//!
+//! ```toml
+//! [default.airlock.lockdown]
+//! mode = "reject"
+//! public = ["/health", "/static/**"]
+//! ```
+//!
+//! * `reject`: requests to routes that are neither covered by a rule nor public, need to be
+//!   authenticated by any of the installed hatches. Otherwise they are rejected with `401 Unauthorized`,
+//!   before any handler runs. So a route that is missing its airlock guard is not silently exposed.
+//! * `abort`: every mounted route needs to be covered by a rule or be public, otherwise the
+//!   launch is aborted. This catches forgotten routes before they reach production. Rocket does not
+//!   tell fairings which guards a route uses, so an [`Authenticated`](crate::Authenticated) or
+//!   [`Guarded`](crate::Guarded) guard in its signature does not count as covered. Such a route
+//!   needs a rule too, or needs to be listed as public, which leaves its guard in charge.
+//!
+//! The mode and public paths can also be set in code with [`Checkpoints::lockdown`] and
+//! [`Checkpoints::public`]. The config takes precedence for the mode and adds to the public paths.
+
+use std::{any::{Any, TypeId}, collections::HashMap, marker::PhantomData, str::FromStr, sync::Arc};
+use glob::{MatchOptions, Pattern};
+use rocket::{
+    Build, Data, error_, info, info_, Orbit, Request, Rocket, Route, warn_,
+    fairing::{AdHoc, Fairing, Info, Kind},
+    figment,
+    http::{Method, Status},
+    route::{Handler, Outcome},
+};
+use serde::Deserialize;
+use yansi::Paint;
+use crate::{Hatch, bulkhead::{Breach, Bulkhead}, registry::{Hatches, Installed}};
+
+
+/// Rank of the routes that enforce the rules. They need to be tried before any other route.
+pub(crate) const CHECKPOINT_RANK: isize = isize::MIN;
+
+const METHODS: [Method; 7] = [Method::Get, Method::Put, Method::Post, Method::Delete, Method::Options, Method::Head, Method::Patch];
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// How to treat routes that are not covered by a rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lockdown {
+    /// Routes that are not covered by a rule are left alone.
+    #[default]
+    Off,
+    /// Requests to routes that are neither covered by a rule nor public need to be authenticated by any hatch.
+    Reject,
+    /// Abort the launch if any route is neither covered by a rule nor public. Guards in the signature
+    /// of a route are not taken into account.
+    Abort,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LockdownConfig {
+    mode: Option<Lockdown>,
+    #[serde(default)]
+    public: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    path: String,
+    #[serde(default)]
+    methods: Vec<String>,
+    hatch: String,
+    policy: Option<String>,
+}
+
+/// A policy registered in code, with the types of its hatch and bulkhead erased.
+#[rocket::async_trait]
+trait Policy: Send + Sync {
+    fn hatch_type(&self) -> TypeId;
+
+    fn hatch_name(&self) -> &'static str;
+
+    async fn inspect(&self, principal: Arc<dyn Any + Send + Sync>, request: &Request<'_>) -> Result<(), Breach>;
+}
+
+struct PolicyOf<H, P>(PhantomData<fn() -> (H, P)>);
+
+#[rocket::async_trait]
+impl<H: Hatch + 'static, P: Bulkhead<H::Principal>> Policy for PolicyOf<H, P> {
+    fn hatch_type(&self) -> TypeId {
+        TypeId::of::<H>()
+    }
+
+    fn hatch_name(&self) -> &'static str {
+        H::name()
+    }
+
+    async fn inspect(&self, principal: Arc<dyn Any + Send + Sync>, request: &Request<'_>) -> Result<(), Breach> {
+        let principal = principal.downcast::<H::Principal>()
+            .map_err(|_| Breach::failure(format!("principal is not one of hatch `{}`", H::name())))?;
+        P::inspect(&principal, request).await
+    }
+}
+
+struct Rule {
+    path: Pattern,
+    methods: Vec<Method>,
+    hatch_name: String,
+    hatch: Arc<dyn Installed>,
+    policy: Option<(String, Arc<dyn Policy>)>,
+}
+
+impl Rule {
+    fn matches(&self, method: Method, path: &str) -> bool {
+        (self.methods.is_empty() || self.methods.contains(&method))
+            && self.path.matches_with(path, MATCH_OPTIONS)
+    }
+
+    fn describe(&self) -> String {
+        match &self.policy {
+            Some((name, _)) => format!("hatch `{}` with policy `{}`", self.hatch_name, name),
+            None => format!("hatch `{}`", self.hatch_name),
+        }
+    }
+}
+
+/// What a request or route is checked against.
+enum Verdict<'m> {
+    Rule(&'m Rule),
+    Public,
+    Lockdown,
+    Unchecked,
+}
+
+/// The compiled rules of the `airlock.routes` table and the lockdown settings.
+struct Manifest {
+    rules: Vec<Rule>,
+    lockdown: Lockdown,
+    public: Vec<Pattern>,
+    hatches: Vec<Arc<dyn Installed>>,
+}
+
+impl Manifest {
+    fn verdict(&self, method: Method, path: &str) -> Verdict<'_> {
+        if let Some(rule) = self.rules.iter().find(|rule| rule.matches(method, path)) {
+            return Verdict::Rule(rule);
+        }
+
+        match self.lockdown {
+            Lockdown::Off => Verdict::Unchecked,
+            _ if self.public.iter().any(|public| public.matches_with(path, MATCH_OPTIONS)) => Verdict::Public,
+            _ => Verdict::Lockdown,
+        }
+    }
+}
+
+/// Fairing that enforces the rules of the `airlock.routes` table. See the [module docs](self).
+#[derive(Clone, Default)]
+pub struct Checkpoints {
+    policies: HashMap<String, Arc<dyn Policy>>,
+    lockdown: Lockdown,
+    public: Vec<String>,
+}
+
+impl Checkpoints {
+    pub fn fairing() -> Self {
+        Checkpoints::default()
+    }
+
+    /// Registers the policy `P` for the hatch `H` under `name`, so rules can reference it.
+    pub fn policy<H: Hatch + 'static, P: Bulkhead<H::Principal>>(mut self, name: &str) -> Self {
+        self.policies.insert(name.to_string(), Arc::new(PolicyOf::<H, P>(PhantomData)));
+        self
+    }
+
+    /// Sets how routes are treated that are not covered by a rule. See [`Lockdown`].
+    pub fn lockdown(mut self, lockdown: Lockdown) -> Self {
+        self.lockdown = lockdown;
+        self
+    }
+
+    /// Marks all paths matching the glob as public, so a lockdown does not apply to them.
+    pub fn public(mut self, path: &str) -> Self {
+        self.public.push(path.to_string());
+        self
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn compile(&self, rocket: &Rocket<Build>) -> Result<Manifest, figment::Error> {
+        let figment = rocket.figment();
+        let hatches = rocket.state::<Hatches>();
+        let rules = self.compile_rules(figment, hatches)?;
+
+        let config = match figment.find_value("airlock.lockdown") {
+            Ok(_) => figment.extract_inner::<LockdownConfig>("airlock.lockdown")?,
+            Err(_) => LockdownConfig::default(),
+        };
+        let error = |msg: String| figment::Error::from(msg).with_path("airlock.lockdown.public");
+        let mut public = self.public.iter()
+            .chain(config.public.iter())
+            .map(|path| Pattern::new(path).map_err(|e| error(format!("invalid path glob `{}`: {}", path, e))))
+            .collect::<Result<Vec<_>, _>>()?;
+        public.extend(hatches.map(|h| h.routes()).unwrap_or_default()
+            .iter()
+            .filter_map(|(_, path)| route_glob(path)));
+
+        Ok(Manifest {
+            rules,
+            lockdown: config.mode.unwrap_or(self.lockdown),
+            public,
+            hatches: hatches.map(|h| h.all()).unwrap_or_default(),
+        })
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn compile_rules(&self, figment: &figment::Figment, hatches: Option<&Hatches>) -> Result<Vec<Rule>, figment::Error> {
+        let configs = match figment.find_value("airlock.routes") {
+            Ok(_) => figment.extract_inner::<Vec<RuleConfig>>("airlock.routes")?,
+            Err(_) => Vec::new(),
+        };
+
+        let error = |msg: String| figment::Error::from(msg).with_path("airlock.routes");
+        configs.into_iter()
+            .map(|config| {
+                let path = Pattern::new(&config.path)
+                    .map_err(|e| error(format!("invalid path glob `{}`: {}", config.path, e)))?;
+                let methods = config.methods.iter()
+                    .map(|m| Method::from_str(m).map_err(|_| error(format!("unknown HTTP method `{}`", m))))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let hatch = hatches.and_then(|h| h.get(&config.hatch))
+                    .ok_or_else(|| error(format!("rule for `{}` references unknown hatch `{}`, installed are: {}",
+                        config.path, config.hatch, hatches.map(|h| h.names().join(", ")).unwrap_or_default())))?;
+                let policy = match config.policy {
+                    Some(name) => {
+                        let policy = self.policies.get(&name)
+                            .ok_or_else(|| error(format!("rule for `{}` references unknown policy `{}`", config.path, name)))?;
+                        if policy.hatch_type() != hatch.hatch_type() {
+                            return Err(error(format!("policy `{}` was registered for hatch `{}`, not for `{}`",
+                                name, policy.hatch_name(), config.hatch)));
+                        }
+                        Some((name, policy.clone()))
+                    },
+                    None => None,
+                };
+
+                Ok(Rule { path, methods, hatch_name: config.hatch, hatch, policy })
+            })
+            .collect()
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Checkpoints {
+    fn info(&self) -> Info {
+        Info { name: "Airlock Checkpoints", kind: Kind::Ignite | Kind::Liftoff }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        // Hatches register themselves while they are ignited. Attaching another fairing defers reading
+        // the rules until all fairings that are attached right now are ignited, no matter their order.
+        let checkpoints = self.clone();
+        Ok(rocket.attach(AdHoc::try_on_ignite("Airlock Checkpoints", |rocket| async move {
+            let emoji = if cfg!(windows) {""} else {"🛡️ "};
+            info!("{}{}", Paint::mask(emoji), Paint::magenta("Airlock Checkpoints:").wrap());
+
+            let manifest = match checkpoints.compile(&rocket) {
+                Ok(manifest) => Arc::new(manifest),
+                Err(e) => {
+                    error_!("Error parsing config for Checkpoints: {}", e);
+                    return Err(rocket);
+                },
+            };
+
+            info_!("Enforcing {} rules", manifest.rules.len());
+            if manifest.lockdown != Lockdown::Off {
+                info_!("Lockdown: {:?}", manifest.lockdown);
+            }
+            if manifest.lockdown == Lockdown::Abort {
+                let unchecked: Vec<_> = rocket.routes()
+                    .filter(|route| matches!(manifest.verdict(route.method, route.uri.path()), Verdict::Lockdown))
+                    .collect();
+                if !unchecked.is_empty() {
+                    for route in unchecked {
+                        error_!("Route {} {} is neither covered by a rule nor public", route.method, route.uri.path());
+                    }
+                    info_!("Guards of a route do not count, add a rule or a public path for routes that rely on them");
+                    error_!("Lockdown aborts the launch");
+                    return Err(rocket);
+                }
+            }
+
+            let routes: Vec<_> = METHODS.iter()
+                .map(|method| {
+                    let mut route = Route::ranked(CHECKPOINT_RANK, *method, "/<_..>", Checkpoint(manifest.clone()));
+                    route.name = Some("airlock_checkpoint".into());
+                    route
+                })
+                .collect();
+            Ok(rocket.mount("/", routes).manage(manifest))
+        })))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(manifest) = rocket.state::<Arc<Manifest>>() else { return };
+
+        info!("{}", Paint::magenta("Airlock Checkpoints:").wrap());
+        for route in rocket.routes().filter(|route| route.rank != CHECKPOINT_RANK) {
+            let path = route.uri.path();
+            let arrow = Paint::new("=>").dim();
+            match manifest.verdict(route.method, path) {
+                Verdict::Rule(rule) => info_!("{} {} {} {}", route.method, path, arrow, rule.describe()),
+                Verdict::Public => info_!("{} {} {} public", route.method, path, arrow),
+                Verdict::Lockdown => info_!("{} {} {} lockdown, any hatch", route.method, path, arrow),
+                Verdict::Unchecked => warn_!("{} {} {} no rule", route.method, path, arrow),
+            }
+        }
+    }
+}
+
+/// Route handler that checks every request against the manifest, before any other route is tried.
+#[derive(Clone)]
+struct Checkpoint(Arc<Manifest>);
+
+impl Checkpoint {
+    async fn admit(&self, request: &Request<'_>) -> Result<(), Status> {
+        match self.0.verdict(request.method(), &request_path(request)) {
+            Verdict::Rule(rule) => {
+                let Some(principal) = rule.hatch.principal(request).await else {
+                    info_!("Checkpoint denied entry: not authenticated by {}", rule.describe());
+                    return Err(Status::Unauthorized);
+                };
+
+                if let Some((name, policy)) = &rule.policy {
+                    if let Err(breach) = policy.inspect(principal, request).await {
+                        info_!("Checkpoint policy `{}` denied passage: {}", name, breach);
+                        return Err(breach.status());
+                    }
+                }
+
+                Ok(())
+            },
+            Verdict::Lockdown => {
+                for hatch in &self.0.hatches {
+                    if hatch.authenticate(request).await {
+                        return Ok(());
+                    }
+                }
+
+                info_!("Lockdown denied entry: not authenticated by any hatch");
+                Err(Status::Unauthorized)
+            },
+            Verdict::Public | Verdict::Unchecked => Ok(()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Handler for Checkpoint {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        match self.admit(request).await {
+            Ok(()) => Outcome::forward(data, Status::NotFound),
+            Err(status) => Outcome::error(status),
+        }
+    }
+}
+
+/// The path of the request as rocket routes it: percent-decoded and without empty segments, e.g.
+/// `/admin/secret` for `//%61dmin/secret`. A `/` that was encoded within a segment stays encoded, so
+/// it can't be mistaken for a separator.
+fn request_path(request: &Request<'_>) -> String {
+    let segments = request.uri().path().segments()
+        .map(|segment| segment.replace('/', "%2F"))
+        .collect::<Vec<_>>();
+    format!("/{}", segments.join("/"))
+}
+
+/// Turns the path of a route into a glob that matches the same paths, e.g. `/login/<id>` into `/login/*`.
+fn route_glob(path: &str) -> Option<Pattern> {
+    let glob = path.split('/')
+        .map(|segment| match segment {
+            s if s.starts_with('<') && s.ends_with("..>") => "**".to_string(),
+            s if s.starts_with('<') && s.ends_with('>') => "*".to_string(),
+            s => Pattern::escape(s),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    Pattern::new(&glob).ok()
+}
@@ -0,0 +1,257 @@
+use std::{any::TypeId, io::Cursor, marker::PhantomData, sync::{Arc, Mutex}, time::Duration};
+use cookie::Key;
+use rocket::{
+    Build, error, error_, info, info_, Orbit, Request, Response, Rocket, Shutdown,
+    fairing::{Fairing, Info, Kind},
+    figment::Figment,
+    http::{uri::Origin, Cookie, Header, Status},
+    tokio::{self, time::sleep},
+};
+use serde::Deserialize;
+use crate::{Airlock, Communicator, Hatch, HatchBuilder, Instance, Mounted, authenticated::{AddedCookies, Challenge, LoginRedirect, RemovedCookies}, checkpoint::CHECKPOINT_RANK, registry::Hatches};
+
+
+/// Where and with which ranks a hatch mounts its routes, as configured in `airlock.<name>.mount`.
+#[derive(Debug, Default, Deserialize)]
+struct MountConfig {
+    base: Option<String>,
+    rank: Option<isize>,
+}
+
+/// How a hatch is obtained, when the fairing is ignited.
+enum Source<H: Hatch> {
+    Config,
+    Comm(H::Comm),
+    Custom(H),
+}
+
+/// Fairing that installs an instance of the hatch `H` into the airlock. Created with [`Airlock::fairing`],
+/// [`Airlock::fairing_with_comm`] or [`Airlock::fairing_custom`].
+///
+/// By default, the routes of the primary instance are mounted at `/` and those of a named [`Instance`]
+/// at `/<instance>`. The base and an offset, that is added to the rank of every route, can be set in
+/// code or in the `airlock.<name>.mount` table, which takes precedence:
+///
+/// ```toml
+/// [default.airlock.openidconnect.mount]
+/// base = "/auth/oidc"
+/// rank = 5
+/// ```
+///
+/// If the communicator of the hatch has a [`refresh_interval`](crate::Communicator::refresh_interval),
+/// the fairing refreshes it in a background task from liftoff until shutdown.
+pub struct HatchFairing<H: Hatch, I: Instance = ()> {
+    source: Mutex<Option<Source<H>>>,
+    base: Option<String>,
+    rank: isize,
+    _instance: PhantomData<fn() -> I>,
+}
+
+impl<H: Hatch + 'static, I: Instance> HatchFairing<H, I> {
+    fn new(source: Source<H>) -> Self {
+        HatchFairing {
+            source: Mutex::new(Some(source)),
+            base: None,
+            rank: 0,
+            _instance: PhantomData,
+        }
+    }
+
+    /// Mounts the routes of the hatch at `base`, e.g. `/auth/oidc`.
+    pub fn mount(mut self, base: &str) -> Self {
+        self.base = Some(base.to_string());
+        self
+    }
+
+    /// Adds `offset` to the rank of every route of the hatch, so they can be ranked below or above
+    /// application routes that collide with them. Ranks never reach the one reserved for the checkpoints.
+    pub fn rank(mut self, offset: isize) -> Self {
+        self.rank = offset;
+        self
+    }
+
+    async fn install(&self, rocket: Rocket<Build>, source: Source<H>) -> rocket::fairing::Result {
+        if TypeId::of::<I>() == TypeId::of::<Mounted>() {
+            error_!("`Mounted` is not an instance of Hatch `{}` and can't be installed.", H::name());
+            return Err(rocket);
+        }
+
+        let name = Airlock::<H, I>::name();
+        let path = format!("airlock.{}.mount", name);
+        let config = match rocket.figment().find_value(&path) {
+            Ok(_) => match rocket.figment().extract_inner::<MountConfig>(&path) {
+                Ok(config) => config,
+                Err(e) => {
+                    error_!("Error parsing mount config for Hatch `{}`: {}", name, e);
+                    return Err(rocket);
+                },
+            },
+            Err(_) => MountConfig::default(),
+        };
+
+        let base = config.base
+            .or_else(|| self.base.clone())
+            .unwrap_or_else(|| I::name().map(|instance| format!("/{}", instance)).unwrap_or_else(|| "/".into()));
+        let base = match Origin::parse_owned(base) {
+            Ok(base) => base,
+            Err(e) => {
+                error_!("Mount base of Hatch `{}` is not a valid path: {}", name, e);
+                return Err(rocket);
+            },
+        };
+        let rank = config.rank.unwrap_or(self.rank);
+
+        let (rocket, mut hatch) = match source {
+            Source::Custom(hatch) => (rocket, hatch),
+            source => {
+                let builder = HatchBuilder::<H>::from(rocket).for_instance(I::name());
+                let builder = match source {
+                    Source::Comm(comm) => builder.with_comm(comm),
+                    _ => builder,
+                };
+                match builder.build().await {
+                    Ok(h) => h,
+                    Err((rocket, e)) => {
+                        log::error!("Error parsing config for Hatch `{}`: {:?}", name, e);//std::any::type_name::<K>()
+                        return Err(rocket);
+                    },
+                }
+            },
+        };
+
+        hatch.docked(&name, &base);
+        info_!("Installing airlock with hatch `{}` at `{}` into rocket", name, base);
+        let routes: Vec<_> = H::routes().into_iter()
+            .map(|mut route| {
+                route.rank = route.rank.saturating_add(rank).max(CHECKPOINT_RANK + 1);
+                route
+            })
+            .collect();
+        let rocket = Hatches::register::<H, I>(rocket, name, Arc::new(hatch), base.clone(), &routes)?;
+        Ok(rocket.mount(base, routes))
+    }
+}
+
+impl<H: Hatch + 'static, I: Instance> Airlock<H, I> {
+    /// Installs the hatch, which is created from the rocket config with [`Hatch::from`].
+    pub fn fairing() -> HatchFairing<H, I> {
+        HatchFairing::new(Source::Config)
+    }
+
+    /// Installs the hatch, which is created from the rocket config, but connected to `comm`.
+    pub fn fairing_with_comm(comm: H::Comm) -> HatchFairing<H, I> {
+        HatchFairing::new(Source::Comm(comm))
+    }
+
+    /// Installs `hatch` as it is.
+    pub fn fairing_custom(hatch: H) -> HatchFairing<H, I> {
+        HatchFairing::new(Source::Custom(hatch))
+    }
+}
+
+#[rocket::async_trait]
+impl<H: Hatch + 'static, I: Instance> Fairing for HatchFairing<H, I> {
+    fn info(&self) -> Info {
+        Info { name: Airlock::<H, I>::fairing_name(), kind: Kind::Ignite | Kind::Liftoff | Kind::Response }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let source = self.source.lock()
+            .expect("Source of hatch is not poisoned")
+            .take();
+        match source {
+            Some(source) => self.install(rocket, source).await,
+            None => {
+                error_!("Hatch `{}` was already installed by this fairing.", Airlock::<H, I>::name());
+                Err(rocket)
+            },
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let name = Airlock::<H, I>::name();
+        let Some(airlock) = rocket.state::<Hatches>().and_then(|hatches| hatches.airlock::<H, I>(&name)) else {
+            return;
+        };
+        if let Some(interval) = airlock.hatch.comm().refresh_interval() {
+            info!("Refreshing Hatch `{}` every {}s", name, interval.as_secs());
+            tokio::spawn(refresh(airlock.hatch, name, rocket.shutdown()));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let removed = request.local_cache(RemovedCookies::<H>::none).names.lock().expect("Removed cookies are not poisoned").clone();
+        for name in removed {
+            if response.cookies().all(|cookie| cookie.name() != name) {
+                let mut cookie = Cookie::build(name).path("/").build();
+                cookie.make_removal();
+                response.adjoin_header(cookie);
+            }
+        }
+
+        let added = request.local_cache(AddedCookies::<H>::none).cookies.lock().expect("Added cookies are not poisoned").clone();
+        let added: Vec<_> = added.into_iter()
+            .filter(|added| response.cookies().all(|cookie| cookie.name() != added.name()))
+            .collect();
+        if !added.is_empty() {
+            match private_cookie_key(request) {
+                Some(key) => {
+                    let mut jar = cookie::CookieJar::new();
+                    for cookie in added {
+                        jar.private_mut(&key).add(cookie);
+                    }
+                    for cookie in jar.delta() {
+                        response.adjoin_header(cookie);
+                    }
+                },
+                None => error_!("The secret key of rocket is not known, so the cookies of Hatch `{}` are lost.", H::name()),
+            }
+        }
+
+        if response.status() != Status::Unauthorized {
+            return;
+        }
+        if let Some(login_uri) = &request.local_cache(LoginRedirect::<H>::none).uri {
+            response.set_status(Status::SeeOther);
+            response.set_header(Header::new("Location", login_uri.to_string()));
+            response.set_sized_body(0, Cursor::new(""));
+        } else if let Some(challenge) = &request.local_cache(Challenge::<H>::none).challenge {
+            response.adjoin_header(Header::new("WWW-Authenticate", challenge.clone()));
+        }
+    }
+}
+
+/// The delay before the first retry of a failed refresh. It doubles with every failure, up to the interval.
+const RETRY: Duration = Duration::from_secs(5);
+
+/// Refreshes the communicator of `hatch` in its refresh interval until rocket shuts down. The interval is
+/// asked for again after every refresh, and refreshing stops once the communicator has none anymore.
+async fn refresh<H: Hatch>(hatch: Arc<H>, name: String, shutdown: Shutdown) {
+    tokio::pin!(shutdown);
+    let mut failures = 0;
+    while let Some(interval) = hatch.comm().refresh_interval() {
+        let delay = match failures {
+            0 => interval,
+            n => RETRY.saturating_mul(1 << (n - 1).min(16)).min(interval),
+        };
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = sleep(delay) => {},
+        }
+
+        match hatch.comm().refresh().await {
+            Ok(()) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                error!("Refreshing Hatch `{}` failed: {}", name, e);
+            },
+        }
+    }
+}
+
+/// The key with which rocket encrypts private cookies. Rocket only reveals it as the `secret_key` its
+/// config provides to a figment.
+fn private_cookie_key(request: &Request<'_>) -> Option<Key> {
+    let master: Vec<u8> = Figment::from(request.rocket().config()).extract_inner("secret_key").ok()?;
+    (master.len() >= 64).then(|| Key::from(&master))
+}
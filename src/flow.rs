@@ -0,0 +1,144 @@
+//! Building blocks shared by the hatches that log users in with a redirect to a provider.
+
+use std::{net::IpAddr, time::Duration};
+#[cfg(all(feature = "oauth2", not(feature = "oidc")))]
+use ::oauth2::CsrfToken;
+#[cfg(feature = "oidc")]
+use openidconnect::CsrfToken;
+use rocket::{
+    error_, warn_,
+    figment::{self, Figment},
+    http::{uri::Origin, Cookie, CookieJar, SameSite, Status},
+};
+use crate::{ReturnTo, state::{LoginState, LoginStates}};
+
+
+/// How long a user may take to log in at the provider.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Why a url of the config can't be resolved, if there is no [`origin`].
+pub(crate) const UNKNOWN_ORIGIN: &str = "the public url of rocket is unknown, set `airlock.public_url` or use an absolute url";
+
+
+/// Scheme and authority at which rocket is reachable, to resolve relative urls. It is `airlock.public_url`
+/// or, without it, derived from the `address` and `port` of rocket. An unspecified address like `0.0.0.0`
+/// does not tell how rocket is reached from the outside, so then it is `None`.
+#[allow(clippy::result_large_err)]
+pub(crate) fn origin(figment: &Figment) -> Result<Option<String>, figment::Error> {
+    if figment.find_value("airlock.public_url").is_ok() {
+        let url = figment.extract_inner::<String>("airlock.public_url")?;
+        return ReturnTo::origin(&url)
+            .map(Some)
+            .ok_or_else(|| figment::Error::from(format!("invalid `public_url`: `{}` is not an http(s) origin", url))
+                .with_path("airlock.public_url"));
+    }
+
+    let address = figment.extract_inner::<IpAddr>("address")?;
+    let port = figment.extract_inner::<u16>("port")?;
+    let scheme = match figment.find_value("tls") {
+        Ok(_) => "https",
+        Err(_) => "http",
+    };
+    Ok(match address {
+        address if address.is_unspecified() => None,
+        IpAddr::V4(address) => Some(format!("{}://{}:{}", scheme, address, port)),
+        IpAddr::V6(address) => Some(format!("{}://[{}]:{}", scheme, address, port)),
+    })
+}
+
+/// Resolves a url from the config against `origin`, if it is relative. `None` if it is relative, but
+/// there is no origin to resolve it against.
+pub(crate) fn absolute(origin: Option<&str>, url: &str) -> Option<String> {
+    match url.starts_with('/') {
+        true => origin.map(|origin| format!("{}{}", origin, url)),
+        false => Some(url.to_string()),
+    }
+}
+
+
+/// The absolute url of the login route of a hatch whose routes are mounted at `base`.
+pub(crate) fn login_url(origin: &str, base: &Origin<'_>) -> String {
+    format!("{}{}/login", origin, base.path().as_str().trim_end_matches('/'))
+}
+
+/// A cookie that survives the round-trip to the provider, which is a cross-site top-level navigation.
+pub(crate) fn cookie(name: String, value: String) -> Cookie<'static> {
+    Cookie::build((name, value))
+        .path("/")
+        .same_site(SameSite::Lax)
+        .http_only(true)
+        .build()
+}
+
+/// Keeps the `nonce`, `pkce_verifier` and `return_to` of a login of the hatch with the cookie `prefix`
+/// under its `state`. The login is bound to the browser by a random id in a private cookie, which is shared by all
+/// logins the browser starts at the hatch, so it can log in in several tabs at once.
+pub(crate) async fn begin(
+    states: &LoginStates,
+    cookies: &CookieJar<'_>,
+    prefix: &str,
+    state: &str,
+    nonce: Option<String>,
+    pkce_verifier: Option<String>,
+    return_to: Option<String>,
+) -> Result<(), Status> {
+    let name = format!("{}_login", prefix);
+    let browser = match cookies.get_private(&name) {
+        Some(browser) => browser.value().to_string(),
+        None => {
+            let browser = CsrfToken::new_random().secret().to_string();
+            cookies.add_private(cookie(name, browser.clone()));
+            browser
+        },
+    };
+
+    let login = LoginState { hatch: prefix.to_string(), browser, nonce, pkce_verifier, return_to };
+    states.store().insert(state.to_string(), login, LOGIN_TIMEOUT).await.map_err(|e| {
+        error_!("Could not store the login state: {}", e);
+        Status::InternalServerError
+    })
+}
+
+/// The `return_to` target of a login, if it is allowed by `allowed`. Other targets are rejected with
+/// `400 Bad Request`, so the login route can not be used as an open redirect.
+pub(crate) fn return_to(allowed: &ReturnTo, return_to: Option<String>) -> Result<Option<String>, Status> {
+    match return_to {
+        Some(target) if !allowed.allows(&target) => {
+            warn_!("Rejecting login that should return to `{}`, which is neither a local path nor on the allowlist.", target);
+            Err(Status::BadRequest)
+        },
+        return_to => Ok(return_to),
+    }
+}
+
+/// The `return_to_origins` of the config table of a hatch, together with `origin` of rocket itself.
+#[allow(clippy::result_large_err)]
+pub(crate) fn return_to_origins(name: &str, origin: Option<&str>, origins: &[String]) -> Result<ReturnTo, figment::Error> {
+    ReturnTo::new(origins.iter().map(String::as_str).chain(origin))
+        .map_err(|entry| invalid(name, "return_to_origins", format!("`{}` is not an http(s) origin", entry)))
+}
+
+/// Takes the login with the `state` the provider returned, if it was started by this browser at the
+/// hatch with the cookie `prefix` and did not expire. Every login can only be completed once.
+pub(crate) async fn complete(states: &LoginStates, cookies: &CookieJar<'_>, prefix: &str, state: &str) -> Result<LoginState, Status> {
+    let login = states.store().take(state).await.map_err(|e| {
+        error_!("Could not load the login state: {}", e);
+        Status::InternalServerError
+    })?;
+    let Some(login) = login else {
+        warn_!("No login flow with the returned state was started, or it expired.");
+        return Err(Status::BadRequest);
+    };
+    let browser = cookies.get_private(&format!("{}_login", prefix));
+    if login.hatch != prefix || browser.is_none_or(|browser| browser.value() != login.browser) {
+        warn_!("The login flow with the returned state was started by another browser or hatch.");
+        return Err(Status::BadRequest);
+    }
+    Ok(login)
+}
+
+/// Creates a config error for `key` of the config table of the hatch `table`.
+pub(crate) fn invalid(table: &str, key: &str, e: impl std::fmt::Display) -> figment::Error {
+    figment::Error::from(format!("invalid `{}`: {}", key, e))
+        .with_path(&format!("airlock.{}.{}", table, key))
+}
@@ -0,0 +1,373 @@
+//! Opaque access tokens. The [`IntrospectionHatch`] authenticates requests with the token in the
+//! `Authorization: Bearer <token>` header, by asking the introspection endpoint of the authorization
+//! server whether it is active, as specified by [RFC 7662](https://www.rfc-editor.org/rfc/rfc7662).
+//! It is configured in the `airlock.introspection` table of the rocket config, e.g.:
+//!
+//! ```toml
+//! [default.airlock.introspection]
+//! introspection_url = "http://localhost:8080/realms/demo/protocol/openid-connect/token/introspect"
+//! # The credentials the hatch authenticates with at the introspection endpoint.
+//! client_id = "my-api"
+//! client_secret = "s3cr3t"
+//! # Optional, the seconds an active token is cached, at most until its `exp`. 0 disables caching.
+//! cache_ttl = 60
+//! # Optional, the seconds an inactive token is cached. 0 disables negative caching.
+//! negative_ttl = 10
+//! # Optional, the number of tokens that are cached at most.
+//! cache_size = 10000
+//! ```
+//!
+//! The cache is keyed by the SHA-256 hash of a token, so tokens are not kept in memory longer than the
+//! request that carries them. Failed requests to the introspection endpoint are never cached.
+//!
+//! The introspection response of an active token is deserialized into the principal `C`, which is
+//! [`TokenInfo`] by default:
+//!
+//! ```rust,ignore
+//! #[get("/api/orders")]
+//! fn orders(token: Authenticated<IntrospectionHatch, Reject>) -> String {
+//!     format!("Orders of {:?}", token.sub)
+//! }
+//!
+//! rocket::build().attach(Airlock::<IntrospectionHatch>::fairing())
+//! ```
+//!
+//! The hatch has no routes. Use it with [`Reject`](crate::Reject), so requests without an active token
+//! are answered with `401 Unauthorized`.
+//!
+//! Only available with the `introspection` feature.
+
+use std::{
+    collections::{BTreeSet, HashMap}, fmt, marker::PhantomData, sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use rocket::{
+    Build, error_, info_, Request, Rocket, warn_,
+    figment::{self, Figment},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use yansi::Paint;
+use crate::{Communicator, Hatch, Result as HatchResult, registry};
+
+
+/// Errors of the [`IntrospectionHatch`] and its [`Introspector`].
+#[derive(Debug)]
+pub enum IntrospectionError {
+    /// The config in `airlock.introspection` is missing or invalid.
+    Config(Box<figment::Error>),
+    /// The introspection endpoint could not be asked or did not answer with a valid response.
+    Request(Box<dyn std::error::Error + Send + Sync>),
+    /// The response for an active token could not be deserialized into the principal.
+    Principal(serde_json::Error),
+}
+
+impl fmt::Display for IntrospectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntrospectionError::Config(e) => write!(f, "invalid introspection config: {}", e),
+            IntrospectionError::Request(e) => write!(f, "token introspection failed: {}", e),
+            IntrospectionError::Principal(e) => write!(f, "invalid introspection response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for IntrospectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IntrospectionError::Config(e) => Some(&**e),
+            IntrospectionError::Request(e) => Some(&**e),
+            IntrospectionError::Principal(e) => Some(e),
+        }
+    }
+}
+
+impl From<figment::Error> for IntrospectionError {
+    fn from(e: figment::Error) -> Self {
+        IntrospectionError::Config(Box::new(e))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct IntrospectionConfig {
+    introspection_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    #[serde(default = "default_cache_ttl")]
+    cache_ttl: u64,
+    #[serde(default = "default_negative_ttl")]
+    negative_ttl: u64,
+    #[serde(default = "default_cache_size")]
+    cache_size: usize,
+}
+
+fn default_cache_ttl() -> u64 {
+    60
+}
+
+fn default_negative_ttl() -> u64 {
+    10
+}
+
+fn default_cache_size() -> usize {
+    10_000
+}
+
+impl IntrospectionConfig {
+    #[allow(clippy::result_large_err)]
+    fn from(figment: &Figment) -> Result<Self, figment::Error> {
+        figment.extract_inner(&format!("airlock.{}", config_name()))
+    }
+}
+
+/// The name of the config table of the hatch, which is the same for every principal.
+fn config_name() -> String {
+    registry::config_name::<IntrospectionHatch>()
+}
+
+/// The members of an introspection response, as defined by RFC 7662.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub active: bool,
+    /// Space separated list of the scopes of the token.
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub token_type: Option<String>,
+    /// Expiry of the token, in seconds since the unix epoch.
+    pub exp: Option<u64>,
+    pub iat: Option<u64>,
+    pub nbf: Option<u64>,
+    pub sub: Option<String>,
+    /// One or many audiences of the token.
+    pub aud: Option<serde_json::Value>,
+    pub iss: Option<String>,
+    pub jti: Option<String>,
+}
+
+/// The communicator of the [`IntrospectionHatch`], which asks the introspection endpoint about tokens.
+pub struct Introspector {
+    http: reqwest::Client,
+    url: String,
+    client_id: String,
+    client_secret: Option<String>,
+}
+
+impl Introspector {
+    /// Creates an introspector that authenticates at `url` with the client credentials.
+    pub fn new(url: String, client_id: String, client_secret: Option<String>) -> Self {
+        Introspector { http: reqwest::Client::new(), url, client_id, client_secret }
+    }
+
+    /// Asks the introspection endpoint about `token` and returns its response.
+    pub async fn introspect(&self, token: &str) -> Result<serde_json::Value, IntrospectionError> {
+        let response = async {
+            self.http.post(&self.url)
+                .basic_auth(form_urlencode(&self.client_id), self.client_secret.as_deref().map(form_urlencode))
+                .header("Accept", "application/json")
+                .form(&[("token", token), ("token_type_hint", "access_token")])
+                .send().await?
+                .error_for_status()?
+                .bytes().await
+        };
+        let body = response.await.map_err(|e| IntrospectionError::Request(Box::new(e)))?;
+        let response: serde_json::Value = serde_json::from_slice(&body)
+            .map_err(|e| IntrospectionError::Request(Box::new(e)))?;
+        match response.get("active").and_then(serde_json::Value::as_bool) {
+            Some(_) => Ok(response),
+            None => Err(IntrospectionError::Request("the response has no `active` member".into())),
+        }
+    }
+}
+
+/// Encodes a client credential as `application/x-www-form-urlencoded`, which it needs to be before it is
+/// used with HTTP Basic authentication, see RFC 6749, section 2.3.1.
+fn form_urlencode(credential: &str) -> String {
+    credential.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' => char::from(byte).to_string(),
+            b' ' => "+".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[rocket::async_trait]
+impl Communicator for Introspector {
+    type Error = IntrospectionError;
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match IntrospectionConfig::from(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        info_!("Introspecting tokens at: {}", Paint::new(&config.introspection_url).underline());
+        Ok((rocket, Introspector::new(config.introspection_url, config.client_id, config.client_secret)))
+    }
+}
+
+/// A cached introspection result. `None` if the token is inactive.
+struct Entry<C> {
+    principal: Option<C>,
+    expires: Instant,
+}
+
+/// Introspection results by the SHA-256 hash of their token, and ordered by when they expire.
+struct Entries<C> {
+    by_key: HashMap<[u8; 32], Entry<C>>,
+    by_expiry: BTreeSet<(Instant, [u8; 32])>,
+}
+
+impl<C> Default for Entries<C> {
+    fn default() -> Self {
+        Entries { by_key: HashMap::new(), by_expiry: BTreeSet::new() }
+    }
+}
+
+impl<C> Entries<C> {
+    fn remove(&mut self, key: &[u8; 32]) -> Option<Entry<C>> {
+        let entry = self.by_key.remove(key)?;
+        self.by_expiry.remove(&(entry.expires, *key));
+        Some(entry)
+    }
+
+    /// Removes the entry that expires first, if it expires before `until`.
+    fn pop_first(&mut self, until: Option<Instant>) -> bool {
+        let Some(&(expires, key)) = self.by_expiry.first() else {
+            return false;
+        };
+        if until.is_some_and(|until| expires > until) {
+            return false;
+        }
+        self.remove(&key).is_some()
+    }
+}
+
+/// Cached introspection results.
+struct Cache<C> {
+    entries: Mutex<Entries<C>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+    size: usize,
+}
+
+impl<C: Clone> Cache<C> {
+    fn key(token: &str) -> [u8; 32] {
+        Sha256::digest(token.as_bytes()).into()
+    }
+
+    /// The cached result for `token`, if there is one that has not expired yet.
+    fn get(&self, token: &str) -> Option<Option<C>> {
+        let entries = self.entries.lock().expect("Introspection cache is not poisoned");
+        entries.by_key.get(&Self::key(token))
+            .filter(|entry| entry.expires > Instant::now())
+            .map(|entry| entry.principal.clone())
+    }
+
+    /// Caches the result for `token`. Active tokens are cached for at most `ttl` and never beyond `exp`,
+    /// inactive ones for `negative_ttl`.
+    fn insert(&self, token: &str, principal: Option<C>, exp: Option<u64>) {
+        let ttl = match &principal {
+            Some(_) => {
+                let remaining = exp.map(|exp| {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                    Duration::from_secs(exp).saturating_sub(now)
+                });
+                remaining.map_or(self.ttl, |remaining| remaining.min(self.ttl))
+            },
+            None => self.negative_ttl,
+        };
+        if ttl.is_zero() || self.size == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let key = Self::key(token);
+        let mut entries = self.entries.lock().expect("Introspection cache is not poisoned");
+        entries.remove(&key);
+        while entries.pop_first(Some(now)) {}
+        while entries.by_key.len() >= self.size && entries.pop_first(None) {}
+        entries.by_expiry.insert((now + ttl, key));
+        entries.by_key.insert(key, Entry { principal, expires: now + ttl });
+    }
+}
+
+/// Hatch that authenticates requests with opaque access tokens. See the [module docs](self).
+pub struct IntrospectionHatch<C = TokenInfo> {
+    introspector: Option<Introspector>,
+    cache: Cache<C>,
+    _principal: PhantomData<fn() -> C>,
+}
+
+impl<C: DeserializeOwned + Clone + Send + Sync + 'static> IntrospectionHatch<C> {
+    /// Introspects `token`, or takes the result from the cache. Returns the principal, if the token is active.
+    pub async fn introspect(&self, token: &str) -> Result<Option<C>, IntrospectionError> {
+        if let Some(principal) = self.cache.get(token) {
+            return Ok(principal);
+        }
+
+        let response = self.comm().introspect(token).await?;
+        let active = response.get("active").and_then(serde_json::Value::as_bool).unwrap_or(false);
+        let exp = response.get("exp").and_then(serde_json::Value::as_u64);
+        let principal = match active {
+            true => Some(serde_json::from_value(response).map_err(IntrospectionError::Principal)?),
+            false => None,
+        };
+        self.cache.insert(token, principal.clone(), exp);
+        Ok(principal)
+    }
+}
+
+#[rocket::async_trait]
+impl<C: DeserializeOwned + Clone + Send + Sync + 'static> Hatch for IntrospectionHatch<C> {
+    type Comm = Introspector;
+    type Error = IntrospectionError;
+    type Principal = C;
+
+    fn comm(&self) -> &Introspector {
+        self.introspector.as_ref().expect("Communicator should have been connected")
+    }
+
+    fn connect_comm(&mut self, comm: Self::Comm) {
+        self.introspector = Some(comm);
+    }
+
+    fn name() -> &'static str {
+        "Introspection"
+    }
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match IntrospectionConfig::from(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        let hatch = IntrospectionHatch {
+            introspector: None,
+            cache: Cache {
+                entries: Mutex::new(Entries::default()),
+                ttl: Duration::from_secs(config.cache_ttl),
+                negative_ttl: Duration::from_secs(config.negative_ttl),
+                size: config.cache_size,
+            },
+            _principal: PhantomData,
+        };
+        Ok((rocket, hatch))
+    }
+
+    async fn authenticate(&self, request: &Request<'_>) -> Option<C> {
+        let token = crate::bearer_token(request)?;
+        match self.introspect(token).await {
+            Ok(Some(principal)) => Some(principal),
+            Ok(None) => {
+                warn_!("Rejecting inactive bearer token");
+                None
+            },
+            Err(e) => {
+                error_!("{}", e);
+                None
+            },
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Bearer tokens for APIs. The [`JwtHatch`] authenticates requests with a JSON Web Token in the
+//! `Authorization: Bearer <token>` header, whose signature is verified with the keys of a JSON Web Key Set.
+//! It is configured in the `airlock.jwt` table of the rocket config, e.g.:
+//!
+//! ```toml
+//! [default.airlock.jwt]
+//! # Either the url of the JWKS, e.g. the `jwks_uri` of an OpenID Provider, or a local file.
+//! jwks_url = "http://localhost:8080/realms/demo/protocol/openid-connect/certs"
+//! # jwks_file = "jwks.json"
+//! # Optional, the `iss` claim is required and verified if set.
+//! issuer = "http://localhost:8080/realms/demo"
+//! # Optional, one or many. The `aud` claim is required and verified if set.
+//! audience = "my-api"
+//! # Optional, seconds of clock skew that are tolerated for `exp` and `nbf`.
+//! leeway = 60
+//! # Optional, the algorithms tokens may be signed with.
+//! algorithms = ["RS256", "ES256", "EdDSA", "HS256"]
+//! # Optional, seconds after which the JWKS is loaded again.
+//! refresh_interval = 3600
+//! # Optional, where the last JWKS that was loaded is stored, to start with it if `jwks_url` is unreachable.
+//! cache = "cache/jwks.json"
+//! ```
+//!
+//! A token is only accepted if it is signed with one of the `algorithms`, by a key of the JWKS that matches
+//! its `kid` and is meant for this algorithm, and if it has not expired. Symmetric keys for `HS256` are
+//! `oct` keys of the JWKS. A token with a `kid` that is not in the JWKS makes the hatch load the JWKS again,
+//! at most every 30 seconds, so keys the provider rotated in are picked up right away. The claims of the token are deserialized into the principal `C`:
+//!
+//! ```rust,ignore
+//! #[derive(Deserialize)]
+//! struct Claims { sub: String, scope: String }
+//!
+//! #[get("/api/orders")]
+//! fn orders(claims: Authenticated<JwtHatch<Claims>, Reject>) -> String {
+//!     format!("Orders of {}", claims.sub)
+//! }
+//!
+//! rocket::build().attach(Airlock::<JwtHatch<Claims>>::fairing())
+//! ```
+//!
+//! The hatch has no routes. Use it with [`Reject`](crate::Reject), so requests without a valid token are
+//! answered with `401 Unauthorized`.
+//!
+//! Only available with the `jwt` feature.
+
+use std::{fmt, marker::PhantomData, path::PathBuf, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+use arc_swap::ArcSwap;
+use jsonwebtoken::{Algorithm, DecodingKey, Header, Validation, jwk::{JwkSet, PublicKeyUse}};
+use rocket::{
+    Build, info_, Request, Rocket, warn_,
+    figment::{self, Figment},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use yansi::Paint;
+use crate::{Communicator, Hatch, offline, Result as HatchResult, registry, rotation::Rotation};
+
+
+/// Errors of the [`JwtHatch`] and its [`Jwks`].
+#[derive(Debug)]
+pub enum JwtError {
+    /// The config in `airlock.jwt` is missing or invalid.
+    Config(Box<figment::Error>),
+    /// The JWKS could not be fetched, read or parsed.
+    Jwks(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for JwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwtError::Config(e) => write!(f, "invalid JWT config: {}", e),
+            JwtError::Jwks(e) => write!(f, "loading the JWKS failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JwtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JwtError::Config(e) => Some(&**e),
+            JwtError::Jwks(e) => Some(&**e),
+        }
+    }
+}
+
+impl From<figment::Error> for JwtError {
+    fn from(e: figment::Error) -> Self {
+        JwtError::Config(Box::new(e))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JwtConfig {
+    jwks_url: Option<String>,
+    jwks_file: Option<PathBuf>,
+    issuer: Option<OneOrMany>,
+    audience: Option<OneOrMany>,
+    #[serde(default = "default_leeway")]
+    leeway: u64,
+    #[serde(default = "default_algorithms")]
+    algorithms: Vec<Algorithm>,
+    refresh_interval: Option<u64>,
+    cache: Option<PathBuf>,
+}
+
+fn default_leeway() -> u64 {
+    60
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256, Algorithm::ES256, Algorithm::EdDSA, Algorithm::HS256]
+}
+
+impl JwtConfig {
+    #[allow(clippy::result_large_err)]
+    fn from(figment: &Figment) -> Result<Self, figment::Error> {
+        figment.extract_inner(&format!("airlock.{}", config_name()))
+    }
+}
+
+/// The name of the config table of the hatch, which is the same for every principal.
+fn config_name() -> String {
+    registry::config_name::<JwtHatch<()>>()
+}
+
+/// A key of the JWKS, ready to verify signatures.
+struct Key {
+    id: Option<String>,
+    /// The algorithm the key is restricted to by its `alg`, if any.
+    algorithm: Option<Algorithm>,
+    key: DecodingKey,
+}
+
+impl Key {
+    /// Prepares the keys of `set` that can verify signatures. Keys for encryption or unsupported
+    /// algorithms are skipped, but fails if no key is left.
+    fn all_of(set: &JwkSet) -> Result<Vec<Key>, JwtError> {
+        let keys: Vec<_> = set.keys.iter()
+            .filter(|jwk| !matches!(jwk.common.public_key_use, Some(PublicKeyUse::Encryption)))
+            .filter_map(|jwk| {
+                let id = jwk.common.key_id.clone();
+                let algorithm = match jwk.common.key_algorithm.map(|alg| alg.to_string().parse::<Algorithm>()) {
+                    Some(Ok(alg)) => Some(alg),
+                    Some(Err(_)) => return None,
+                    None => None,
+                };
+                match DecodingKey::from_jwk(jwk) {
+                    Ok(key) => Some(Key { id, algorithm, key }),
+                    Err(e) => {
+                        warn_!("Skipping key {} of JWKS: {}", id.as_deref().unwrap_or("without id"), e);
+                        None
+                    },
+                }
+            })
+            .collect();
+
+        match keys.is_empty() {
+            true => Err(JwtError::Jwks("the JWKS contains no key to verify signatures".into())),
+            false => Ok(keys),
+        }
+    }
+
+    /// The keys that may have signed a token with `kid` and `algorithm`. Without `kid`, these are all keys
+    /// that are not restricted to another algorithm.
+    fn candidates<'a>(keys: &'a [Key], kid: Option<&'a str>, algorithm: Algorithm) -> impl Iterator<Item = &'a DecodingKey> + 'a {
+        keys.iter()
+            .filter(move |key| kid.is_none() || key.id.as_deref() == kid)
+            .filter(move |key| key.algorithm.is_none_or(|alg| alg == algorithm))
+            .map(|key| &key.key)
+    }
+}
+
+/// Where a JWKS is loaded from.
+#[derive(Debug, Clone)]
+pub enum JwksSource {
+    Url(String),
+    File(PathBuf),
+}
+
+impl JwksSource {
+    async fn fetch(&self) -> Result<JwkSet, JwtError> {
+        let body = match self {
+            JwksSource::Url(url) => {
+                let body = async {
+                    reqwest::get(url).await?
+                        .error_for_status()?
+                        .bytes().await
+                };
+                body.await.map_err(|e| JwtError::Jwks(Box::new(e)))?.to_vec()
+            },
+            JwksSource::File(path) => rocket::tokio::fs::read(path).await.map_err(|e| JwtError::Jwks(Box::new(e)))?,
+        };
+        serde_json::from_slice(&body).map_err(|e| JwtError::Jwks(Box::new(e)))
+    }
+}
+
+impl fmt::Display for JwksSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwksSource::Url(url) => f.write_str(url),
+            JwksSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// The communicator of the [`JwtHatch`]. It holds the keys of the JSON Web Key Set, which is loaded
+/// when the hatch is installed. If the JWKS was loaded from a [`JwksSource`], it is loaded again every
+/// `refresh_interval` seconds, if configured, and whenever a token names a key id that is not in the set.
+/// The keys are swapped atomically, so requests are never blocked by a refresh.
+///
+/// With a `cache` path, every JWKS that was loaded is stored there. If the source is unreachable when
+/// the hatch is installed, the stored JWKS is used instead and the source is tried again in the background.
+pub struct Jwks {
+    keys: ArcSwap<Vec<Key>>,
+    source: Option<JwksSource>,
+    cache: Option<PathBuf>,
+    refresh_interval: Option<Duration>,
+    /// Whether the keys were restored from the cache and not loaded from the source since.
+    restored: AtomicBool,
+    rotation: Rotation,
+}
+
+impl Jwks {
+    /// Prepares the keys of `set` that can verify signatures. Keys for encryption or unsupported
+    /// algorithms are skipped, but fails if no key is left. The keys are never refreshed.
+    pub fn new(set: &JwkSet) -> Result<Self, JwtError> {
+        Ok(Jwks::with_keys(Key::all_of(set)?, None))
+    }
+
+    /// Loads the JWKS from `source`.
+    pub async fn load(source: JwksSource) -> Result<Self, JwtError> {
+        let keys = Key::all_of(&source.fetch().await?)?;
+        Ok(Jwks::with_keys(keys, Some(source)))
+    }
+
+    /// Loads the JWKS from `source` and stores it at `cache`. If that fails, the JWKS that was stored at
+    /// `cache` before is used and `source` is tried again every `refresh_interval`, or every 5 minutes
+    /// until it was loaded.
+    pub async fn load_or_restore(source: JwksSource, cache: PathBuf) -> Result<Self, JwtError> {
+        let mut jwks = match source.fetch().await {
+            Ok(set) => {
+                let keys = Key::all_of(&set)?;
+                offline::save(&cache, &set).await;
+                Jwks::with_keys(keys, Some(source))
+            },
+            Err(e) => {
+                let Some(set) = offline::restore::<JwkSet>(&cache).await else {
+                    return Err(e);
+                };
+                warn_!("{}", e);
+                warn_!("Using JWKS from cache `{}` until {} is reachable", cache.display(), source);
+                let jwks = Jwks::with_keys(Key::all_of(&set)?, Some(source));
+                jwks.restored.store(true, Ordering::Relaxed);
+                jwks
+            },
+        };
+        jwks.cache = Some(cache);
+        Ok(jwks)
+    }
+
+    fn with_keys(keys: Vec<Key>, source: Option<JwksSource>) -> Self {
+        Jwks {
+            keys: ArcSwap::from_pointee(keys),
+            source,
+            cache: None,
+            refresh_interval: None,
+            restored: AtomicBool::new(false),
+            rotation: Rotation::default(),
+        }
+    }
+
+    /// Loads the JWKS again every `interval`, while rocket is in orbit.
+    pub fn refresh_every(mut self, interval: Duration) -> Self {
+        self.refresh_interval = Some(interval);
+        self
+    }
+
+    /// The number of keys that can verify signatures.
+    pub fn len(&self) -> usize {
+        self.keys.load().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.load().is_empty()
+    }
+
+    /// Whether the set contains a key with the id `kid`.
+    pub fn contains(&self, kid: &str) -> bool {
+        self.keys.load().iter().any(|key| key.id.as_deref() == Some(kid))
+    }
+
+    /// Refreshes the keys, because a token named a key id that is not in the set. Returns whether they were
+    /// refreshed, which they are not, if they were refreshed just before or there is no source to refresh from.
+    async fn rotate(&self) -> bool {
+        if self.source.is_none() {
+            return false;
+        }
+        self.rotation.rotate(async {
+            match self.refresh().await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn_!("{}", e);
+                    false
+                },
+            }
+        }).await
+    }
+}
+
+#[rocket::async_trait]
+impl Communicator for Jwks {
+    type Error = JwtError;
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match JwtConfig::from(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        let source = match (config.jwks_url, config.jwks_file) {
+            (Some(url), None) => JwksSource::Url(url),
+            (None, Some(path)) => JwksSource::File(path),
+            _ => {
+                let e = figment::Error::from("either `jwks_url` or `jwks_file` needs to be set")
+                    .with_path(&format!("airlock.{}", config_name()));
+                return Err((rocket, e.into()));
+            },
+        };
+        info_!("Loading JWKS from: {}", Paint::new(&source).underline());
+        let jwks = match config.cache {
+            Some(cache) => Jwks::load_or_restore(source, cache).await,
+            None => Jwks::load(source).await,
+        };
+        match jwks {
+            Ok(mut jwks) => {
+                info_!("Loaded {} keys", jwks.len());
+                if let Some(interval) = config.refresh_interval {
+                    jwks.refresh_interval = Some(Duration::from_secs(interval));
+                }
+                Ok((rocket, jwks))
+            },
+            Err(e) => Err((rocket, e)),
+        }
+    }
+
+    fn refresh_interval(&self) -> Option<Duration> {
+        self.source.as_ref()?;
+        match self.refresh_interval {
+            Some(interval) => Some(interval),
+            None if self.restored.load(Ordering::Relaxed) => Some(offline::RETRY_INTERVAL),
+            None => None,
+        }
+    }
+
+    async fn refresh(&self) -> Result<(), Self::Error> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+        let set = source.fetch().await?;
+        let keys = Key::all_of(&set)?;
+        if let Some(cache) = &self.cache {
+            offline::save(cache, &set).await;
+        }
+        info_!("Refreshed JWKS from {}: {} keys", source, keys.len());
+        self.keys.store(Arc::new(keys));
+        self.restored.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Hatch that authenticates requests with a JSON Web Token. See the [module docs](self).
+pub struct JwtHatch<C> {
+    jwks: Option<Jwks>,
+    validation: Validation,
+    _claims: PhantomData<fn() -> C>,
+}
+
+impl<C: DeserializeOwned + Send + Sync + 'static> JwtHatch<C> {
+    /// Verifies `token` and deserializes its claims.
+    pub fn verify(&self, token: &str) -> Result<C, jsonwebtoken::errors::Error> {
+        use jsonwebtoken::errors::ErrorKind;
+
+        let header = jsonwebtoken::decode_header(token)?;
+        if !self.validation.algorithms.contains(&header.alg) {
+            return Err(ErrorKind::InvalidAlgorithm.into());
+        }
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![header.alg];
+
+        let keys = self.comm().keys.load();
+        let mut result = Err(ErrorKind::InvalidSignature.into());
+        for key in Key::candidates(&keys, header.kid.as_deref(), header.alg) {
+            result = jsonwebtoken::decode::<C>(token, key, &validation).map(|data| data.claims);
+            match &result {
+                Err(e) if matches!(e.kind(), ErrorKind::InvalidSignature | ErrorKind::InvalidAlgorithm) => continue,
+                _ => break,
+            }
+        }
+        result
+    }
+}
+
+#[rocket::async_trait]
+impl<C: DeserializeOwned + Send + Sync + 'static> Hatch for JwtHatch<C> {
+    type Comm = Jwks;
+    type Error = JwtError;
+    type Principal = C;
+
+    fn comm(&self) -> &Jwks {
+        self.jwks.as_ref().expect("Communicator should have been connected")
+    }
+
+    fn connect_comm(&mut self, comm: Self::Comm) {
+        self.jwks = Some(comm);
+    }
+
+    fn name() -> &'static str {
+        "JWT"
+    }
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match JwtConfig::from(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+        if config.algorithms.is_empty() {
+            let e = figment::Error::from("at least one algorithm needs to be allowed")
+                .with_path(&format!("airlock.{}.algorithms", config_name()));
+            return Err((rocket, e.into()));
+        }
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.algorithms = config.algorithms;
+        validation.leeway = config.leeway;
+        validation.validate_nbf = true;
+        validation.validate_aud = config.audience.is_some();
+        if let Some(issuer) = config.issuer {
+            validation.set_issuer(&issuer.into_vec());
+            validation.required_spec_claims.insert("iss".into());
+        }
+        if let Some(audience) = config.audience {
+            validation.set_audience(&audience.into_vec());
+            validation.required_spec_claims.insert("aud".into());
+        }
+
+        let hatch = JwtHatch {
+            jwks: None,
+            validation,
+            _claims: PhantomData,
+        };
+        Ok((rocket, hatch))
+    }
+
+    async fn authenticate(&self, request: &Request<'_>) -> Option<C> {
+        let token = crate::bearer_token(request)?;
+        if let Ok(Header { kid: Some(kid), .. }) = jsonwebtoken::decode_header(token) {
+            if !self.comm().contains(&kid) {
+                info_!("Token is signed with unknown key `{}`, refreshing JWKS", kid);
+                self.comm().rotate().await;
+            }
+        }
+
+        match self.verify(token) {
+            Ok(claims) => Some(claims),
+            Err(e) => {
+                warn_!("Rejecting bearer token: {}", e);
+                None
+            },
+        }
+    }
+}
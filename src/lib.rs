@@ -4,14 +4,50 @@
 // - compartment
 // - bulkhead
 
-use std::{convert::Infallible, marker::Sized, sync::Arc};
+use std::{any::{type_name, TypeId}, convert::Infallible, marker::{PhantomData, Sized}, sync::Arc, time::Duration};
 use rocket::{
-    Build, info_, info, Rocket, Route, State,
-    fairing::{AdHoc, Fairing},
+    Build, error, error_, Ignite, info_, info, Rocket, Route, Sentinel,
+    figment::{self, Figment, providers::Serialized, value::{Dict, Value}},
+    http::{ext::IntoOwned, uri::Origin, Status},
     request::{FromRequest, Outcome, Request}
 };
 use yansi::Paint;
 
+mod authenticated;
+#[cfg(feature = "basic")]
+pub mod basic;
+pub mod bulkhead;
+pub mod checkpoint;
+mod fairing;
+#[cfg(any(feature = "oidc", feature = "oauth2"))]
+mod flow;
+#[cfg(feature = "introspection")]
+pub mod introspection;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+#[cfg(feature = "oauth2")]
+pub mod oauth2;
+#[cfg(any(feature = "jwt", feature = "oidc"))]
+mod offline;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+#[cfg(any(feature = "oidc", feature = "oauth2"))]
+pub mod provider;
+pub mod rbac;
+mod registry;
+#[cfg(any(feature = "jwt", feature = "oidc"))]
+mod rotation;
+#[cfg(feature = "oidc")]
+pub mod session;
+#[cfg(any(feature = "oidc", feature = "oauth2"))]
+pub mod state;
+
+pub use authenticated::{Authenticated, Forward, OnFailure, RedirectToLogin, Reject, RETURN_TO, ReturnTo};
+pub use bulkhead::{Breach, Bulkhead, Guarded};
+pub use checkpoint::Checkpoints;
+pub use fairing::HatchFairing;
+use registry::Hatches;
+
 
 pub type Result<T, E> = std::result::Result<(Rocket<Build>, T), (Rocket<Build>, E)>;
 
@@ -23,6 +59,17 @@ pub trait Communicator: Send + Sync {
     async fn from(rocket: Rocket<Build>) -> Result<Self, Self::Error>
     where
         Self: Sized;
+
+    /// How often the information of mission control, e.g. its keys or metadata, goes stale. If this returns
+    /// a duration, the fairing of the hatch calls [`Communicator::refresh`] in that interval from liftoff
+    /// until shutdown, and retries with a backoff if it fails. It is asked again after every refresh, so the
+    /// interval may change, and refreshing stops once it returns `None`. The standard implementation never
+    /// refreshes.
+    fn refresh_interval(&self) -> Option<Duration> { None }
+
+    /// Fetches the information of mission control again. It is called while requests are handled, so the
+    /// new information should be swapped in atomically, e.g. with an `ArcSwap`, instead of blocking them.
+    async fn refresh(&self) -> std::result::Result<(), Self::Error> { Ok(()) }
 }
 
 #[rocket::async_trait]
@@ -40,6 +87,9 @@ pub trait Hatch: Send + Sync {
     /// If you don't need a chatty Hatch, then just use () as your Comm type.
     type Comm: Communicator;
     type Error: std::error::Error;
+    /// The identity a Hatch grants entry to, after a request passed all of its security checks.
+    /// This can be as simple as a username or as rich as the full set of claims of an ID token.
+    type Principal: Send + Sync + 'static;
 
     /// This is like an intercom, press the button and speak into it, or in this case, call
     /// the function and us the `Comm` to speak to your mission control.
@@ -57,69 +107,175 @@ pub trait Hatch: Send + Sync {
     /// function can be ignored, as the standard implementation will then return an empty vector.
     fn routes() -> Vec<Route> { Vec::new() }
 
+    /// The Uri of the route at which a user can log in, relative to where the routes of the Hatch are
+    /// mounted. It is used by [`Authenticated`] guards with the [`RedirectToLogin`] failure behaviour.
+    /// If a Hatch has no such route, then this function can be ignored, as the standard implementation
+    /// will then return `None`.
+    fn login_uri(&self) -> Option<Origin<'static>> { None }
+
+    /// The challenge of the `WWW-Authenticate` header, e.g. `Basic realm="tools"`, with which a
+    /// `401 Unauthorized` response asks for credentials. It is sent by [`Authenticated`] guards with the
+    /// [`Reject`] failure behaviour, or with [`RedirectToLogin`] if the Hatch has no login route. If a Hatch
+    /// does not use an HTTP authentication scheme, then this function can be ignored, as the standard
+    /// implementation will then return `None`.
+    fn challenge(&self) -> Option<String> { None }
+
+    /// Called when the Hatch is installed, with the name of its [`Instance`] and the path at which its
+    /// routes are mounted. Use this, if the Hatch needs to know where it lives, e.g. to keep the state of
+    /// several instances apart or to generate uris to its routes. Can be ignored otherwise.
+    #[allow(unused_variables)]
+    fn docked(&mut self, name: &str, base: &Origin<'static>) {}
+
     /// With this function a Hatch can be created and configured with parameters that are present in
     /// rockets config file. It is async so you can fully configure your hatch, even if you need to
     /// do some delaying task, such as discovering an OpenID Connect manifest at a remote provider.
     async fn from(rocket: Rocket<Build>) -> Result<Self, Self::Error>
     where
         Self: Sized;
+
+    /// Runs the security checks of the Hatch against an incoming request. This is the single place
+    /// where credentials are extracted from the request (cookies, headers, ...) and verified, if
+    /// necessary by using the `Comm` to speak with mission control. Returns the authenticated
+    /// `Principal`, or `None` if the request carries no or invalid credentials.
+    async fn authenticate(&self, request: &Request<'_>) -> Option<Self::Principal>;
+}
+
+/// Marks an instance of a hatch. Every instance of a hatch has its own config table, mounts its own
+/// routes and has its own cache of authenticated principals, so the same hatch type can be installed
+/// several times, e.g. for two different identity providers. `()` is the primary instance, it reads the
+/// config table named after the hatch and mounts its routes at `/`. Any [`Label`](bulkhead::Label) is a
+/// named instance, it reads `airlock.<label>` and by default mounts its routes at `/<label>`.
+///
+/// Guards always use the instance they name, the primary instance included. It is not replaced by a
+/// named instance, even if that is the only one installed, so a route can't end up with the config of
+/// another identity provider. If the instance is missing, the launch is aborted. The routes of a hatch
+/// are the exception, they use [`Mounted`] to reach the instance that mounted them.
+///
+/// ```rust,ignore
+/// rocket_airlock::label!(Employees = "employees");
+/// rocket_airlock::label!(Customers = "customers");
+///
+/// #[get("/staff")]
+/// fn staff(user: Authenticated<OidcHatch, Forward, Employees>) -> String {
+///     format!("Hello colleague: {}", user.name)
+/// }
+///
+/// rocket::build()
+///     .attach(Airlock::<OidcHatch, Employees>::fairing())
+///     .attach(Airlock::<OidcHatch, Customers>::fairing())
+/// ```
+pub trait Instance: Send + Sync + 'static {
+    /// Name of the instance, or `None` for the primary instance.
+    fn name() -> Option<&'static str>;
+}
+
+impl Instance for () {
+    fn name() -> Option<&'static str> { None }
+}
+
+impl<L: bulkhead::Label> Instance for L {
+    fn name() -> Option<&'static str> { Some(L::NAME) }
+}
+
+/// The instance of a hatch that mounted the route which is handling the request. The routes of a hatch
+/// don't know for which instance they are mounted, so they use `Airlock<H, Mounted>` to reach it, e.g.
+/// to run a login with the config of that instance. It is not an instance of its own and can't be
+/// installed, outside of the routes of a hatch it falls back to the primary instance.
+pub struct Mounted;
+
+impl Instance for Mounted {
+    fn name() -> Option<&'static str> { None }
 }
 
 /// The security airlock is the entry point to a rocket. Everything from the outside environment
 /// that wants to enter a rocket, needs to go through its hatches and pass all their security checks.
-pub struct Airlock<H: Hatch> { pub hatch: Arc<H> }
-
-impl<H: Hatch + 'static> Airlock<H> {
-    pub fn fairing() -> impl Fairing {
-        AdHoc::try_on_ignite(H::name(), |rocket| async {
-            let (rocket, hatch) = match HatchBuilder::<H>::from(rocket)
-                .build()
-                .await {
-                    Ok(h) => h,
-                    Err((rocket, e)) => {
-                        log::error!("Error parsing config for Hatch `{}`: {:?}", H::name(), e);//std::any::type_name::<K>()
-                        return Err(rocket);
-                    },
-                };
-
-            Ok(Self::finish_setup(rocket, hatch))
-        })
+pub struct Airlock<H: Hatch, I: Instance = ()> {
+    pub hatch: Arc<H>,
+    base: Origin<'static>,
+    _instance: PhantomData<fn() -> I>,
+}
+
+impl<H: Hatch + 'static, I: Instance> Airlock<H, I> {
+    /// Name of this instance of the hatch, which is also the name of its config table `airlock.<name>`.
+    /// It is the name of the [`Instance`] or, for the primary instance, the name of the hatch.
+    pub fn name() -> String {
+        I::name().map(String::from).unwrap_or_else(registry::config_name::<H>)
     }
 
-    pub fn fairing_with_comm(comm: H::Comm) -> impl Fairing {
-        AdHoc::try_on_ignite(H::name(), |rocket| async {
-            let (rocket, hatch) = match HatchBuilder::<H>::from(rocket)
-                .with_comm(comm)
-                .build()
-                .await {
-                    Ok(h) => h,
-                    Err((rocket, e)) => {
-                        log::error!("Error parsing config for Hatch `{}`: {:?}", H::name(), e);//std::any::type_name::<K>()
-                        return Err(rocket);
-                    },
-                };
-
-            Ok(Self::finish_setup(rocket, hatch))
-        })
+    /// The path at which this instance of the hatch mounted its routes.
+    pub fn base(&self) -> &Origin<'static> {
+        &self.base
+    }
+
+    /// Turns the uri of a route of the hatch into the uri at which this instance mounted it, e.g.
+    /// `/login` into `/auth/oidc/login`, if the routes are mounted at `/auth/oidc`.
+    pub fn uri<'u>(&self, uri: impl Into<Origin<'u>>) -> Origin<'static> {
+        let uri = uri.into();
+        let base = self.base.path().as_str().trim_end_matches('/');
+        Origin::parse_owned(format!("{}{}", base, uri))
+            .unwrap_or_else(|_| uri.into_owned())
     }
 
-    pub fn fairing_custom(hatch: H) -> impl Fairing {
-        AdHoc::try_on_ignite(H::name(), |rocket| async {
-            Ok(Self::finish_setup(rocket, hatch))
+    /// Authenticates the request with the hatch of this airlock. The outcome is cached, so a request is
+    /// authenticated at most once per hatch, no matter how many guards or fairings ask for its principal.
+    /// In contrast to calling [`Hatch::authenticate`] directly, this is cheap to call repeatedly.
+    pub async fn authenticate(request: &Request<'_>) -> Option<Arc<H::Principal>> {
+        request.local_cache_async(async {
+            let principal = match Self::docked(request) {
+                Some(airlock) => airlock.hatch.authenticate(request).await.map(Arc::new),
+                None => None,
+            };
+            Checked::<H, I>(principal, PhantomData)
         })
+        .await
+        .0
+        .clone()
     }
 
-    fn finish_setup(rocket: Rocket<Build>, hatch: H) -> Rocket<Build> {
-        info_!("Installing airlock with hatch into rocket");
-        rocket.manage(Arc::new(hatch))
-            .mount("/", H::routes())
+    pub(crate) fn docked_at(hatch: Arc<H>, base: Origin<'static>) -> Self {
+        Airlock { hatch, base, _instance: PhantomData }
+    }
+
+    fn docked(request: &Request<'_>) -> Option<Self> {
+        let hatches = request.rocket().state::<Hatches>()?;
+        // The routes of a hatch do not know for which instance they were mounted. So they are
+        // served by whichever instance mounted the route that is handling the request.
+        if I::name().is_none() {
+            if let Some(airlock) = request.route().and_then(|route| hatches.airlock_of(route)) {
+                return Some(airlock);
+            }
+        }
+
+        request.local_cache(|| Docked::<H, I>(hatches.airlock(&Self::name())))
+            .0
+            .clone()
+    }
+
+    fn report_missing() -> bool {
+        error!("Hatch `{}` is used by a route, but was not installed into the airlock.", Self::name());
+        match I::name() {
+            Some(_) => info_!("Attach its fairing, e.g. `.attach(Airlock::<{}, {}>::fairing())`.", type_name::<H>(), type_name::<I>()),
+            None => info_!("Attach its fairing, e.g. `.attach(Airlock::<{}>::fairing())`.", type_name::<H>()),
+        }
+        true
+    }
+
+    fn fairing_name() -> &'static str {
+        I::name().unwrap_or(H::name())
+    }
+}
+
+impl<H: Hatch, I: Instance> Clone for Airlock<H, I> {
+    fn clone(&self) -> Self {
+        Airlock { hatch: self.hatch.clone(), base: self.base.clone(), _instance: PhantomData }
     }
 }
 
 struct HatchBuilder<H: Hatch> {
     rocket: Rocket<Build>,
     comm: Option<H::Comm>,
-    hatch: Option<H>
+    hatch: Option<H>,
+    instance: Option<&'static str>,
 }
 
 impl<H: Hatch + 'static> HatchBuilder<H>{
@@ -127,7 +283,8 @@ impl<H: Hatch + 'static> HatchBuilder<H>{
         HatchBuilder {
             rocket,
             comm: None,
-            hatch: None
+            hatch: None,
+            instance: None,
         }
     }
 
@@ -142,44 +299,125 @@ impl<H: Hatch + 'static> HatchBuilder<H>{
         self
     }
 
+    /// Builds a named instance, which reads its config from `airlock.<instance>`.
+    fn for_instance(mut self, instance: Option<&'static str>) -> Self {
+        self.instance = instance;
+        self
+    }
+
     async fn build(self) -> std::result::Result<(Rocket<Build>, H), (Rocket<Build>, Box<dyn std::error::Error>)> {
         let emoji = if cfg!(windows) {""} else {"🛡️ "};
-        info!("{}{}", Paint::mask(emoji), Paint::magenta(&format!("Airlock Hatch {}:", Paint::blue(H::name()))).wrap());
+        let name = match self.instance {
+            Some(instance) => format!("{} ({})", H::name(), instance),
+            None => H::name().to_string(),
+        };
+        info!("{}{}", Paint::mask(emoji), Paint::magenta(&format!("Airlock Hatch {}:", Paint::blue(&name))).wrap());
+
+        // A named instance sees its own config table in place of the one of the hatch, while it is built.
+        let (rocket, original) = match self.instance {
+            Some(instance) => {
+                let original = self.rocket.figment().clone();
+                match focus::<H>(&original, instance) {
+                    Ok(figment) => (self.rocket.configure(figment), Some(original)),
+                    Err(e) => return Err((self.rocket, e.into())),
+                }
+            },
+            None => (self.rocket, None),
+        };
 
-        let rocket = self.rocket;
         let (rocket, mut hatch) = if let Some(hatch) = self.hatch {
             info_!("Using provided hatch: `{}`", H::name());
             (rocket, hatch)
         } else {
             info_!("Extracting config from Rocket");
-            H::from(rocket).await
-                .map_err(|(rocket, e)| (rocket, e.into()))?
+            match H::from(rocket).await {
+                Ok(built) => built,
+                Err((rocket, e)) => return Err((restore(rocket, original), e.into())),
+            }
         };
 
         let (rocket, comm) = if let Some(comm) = self.comm {
             info_!("Connecting custom Communicator");
             (rocket, comm)
         } else {
-            <H::Comm as Communicator>::from(rocket).await
-                .map_err(|(rocket, e)| (rocket, e.into()))?
+            match <H::Comm as Communicator>::from(rocket).await {
+                Ok(connected) => connected,
+                Err((rocket, e)) => return Err((restore(rocket, original), e.into())),
+            }
         };
         hatch.connect_comm(comm);
 
-        Ok((rocket, hatch))
+        Ok((restore(rocket, original), hatch))
+    }
+}
+
+/// Gives the rocket back its `original` figment, if it was focused on a named instance.
+fn restore(rocket: Rocket<Build>, original: Option<Figment>) -> Rocket<Build> {
+    match original {
+        Some(figment) => rocket.configure(figment),
+        None => rocket,
+    }
+}
+
+/// The token of the `Authorization: Bearer <token>` header of `request`, if it has one.
+#[cfg(any(feature = "jwt", feature = "introspection"))]
+pub(crate) fn bearer_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    let header = request.headers().get_one("Authorization")?;
+    let (scheme, token) = header.split_once(' ')?;
+    match scheme.eq_ignore_ascii_case("Bearer") {
+        true => Some(token.trim()).filter(|token| !token.is_empty()),
+        false => None,
+    }
+}
+
+/// Returns the config of `figment` with the table `airlock.<instance>` in place of the table of the hatch `H`.
+#[allow(clippy::result_large_err)]
+fn focus<H: Hatch>(figment: &Figment, instance: &str) -> std::result::Result<Figment, figment::Error> {
+    let mut config = figment.extract::<Dict>()?;
+    if let Some(Value::Dict(_, airlock)) = config.get_mut("airlock") {
+        let hatch = registry::config_name::<H>();
+        match airlock.get(instance).cloned() {
+            Some(table) => airlock.insert(hatch, table),
+            None => airlock.remove(&hatch),
+        };
     }
+
+    Ok(Figment::from(Serialized::globals(config)).select(figment.profile().clone()))
 }
 
 #[rocket::async_trait]
-impl<'r, H: Hatch + 'static> FromRequest<'r> for Airlock<H> {
+impl<'r, H: Hatch + 'static, I: Instance> FromRequest<'r> for Airlock<H, I> {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        match request.guard::<&State<Arc<H>>>().await {
-            Outcome::Success(h) => Outcome::Success(Airlock {
-                hatch: h.inner().clone(),
-            }),
-            Outcome::Error(e) => Outcome::Error(e),
-            Outcome::Forward(f) => Outcome::Forward(f),
+        match Self::docked(request) {
+            Some(airlock) => Outcome::Success(airlock),
+            None => {
+                error_!("Hatch `{}` was not installed into the airlock.", Self::name());
+                Outcome::Error((Status::InternalServerError, ()))
+            },
         }
     }
 }
+
+impl<H: Hatch + 'static, I: Instance> Sentinel for Airlock<H, I> {
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        let Some(hatches) = rocket.state::<Hatches>() else {
+            return Self::report_missing();
+        };
+
+        // The routes of a hatch are mounted by every instance, so any installed instance will do for
+        // them. Everything else needs exactly the instance it names, the primary one included.
+        let installed = match TypeId::of::<I>() == TypeId::of::<Mounted>() {
+            true => hatches.contains::<H>(),
+            false => hatches.airlock::<H, I>(&Self::name()).is_some(),
+        };
+        !installed && Self::report_missing()
+    }
+}
+
+/// The instance of a hatch, looked up once per request.
+struct Docked<H: Hatch, I: Instance>(Option<Airlock<H, I>>);
+
+/// The outcome of the security checks of an instance of a hatch, run at most once per request.
+struct Checked<H: Hatch, I>(Option<Arc<H::Principal>>, PhantomData<fn() -> I>);
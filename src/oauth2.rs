@@ -0,0 +1,519 @@
+//! Generic OAuth 2.0 client, for providers that do not speak OpenID Connect. The [`OAuth2Hatch`] logs
+//! users in with the authorization code flow and asks the userinfo endpoint of the provider who they are.
+//! Its endpoints are configured in the `airlock.oauth2` table of the rocket config, e.g.:
+//!
+//! ```toml
+//! [default.airlock.oauth2]
+//! auth_url = "https://github.com/login/oauth/authorize"
+//! token_url = "https://github.com/login/oauth/access_token"
+//! userinfo_url = "https://api.github.com/user"
+//! client_id = "my-app"
+//! client_secret = "s3cr3t"
+//! # Optional, defaults to the login route of the hatch at `airlock.public_url`, or at the `address`
+//! # and `port` of rocket, unless it listens on an unspecified address like `0.0.0.0`.
+//! redirect_url = "https://app.example.com/login"
+//! # Optional.
+//! scopes = ["read:user"]
+//! # Optional, PKCE with S256 is used unless disabled.
+//! pkce = true
+//! # Optional, other origins a login may return to, besides local paths.
+//! return_to_origins = ["https://portal.example.com"]
+//! ```
+//!
+//! What the userinfo endpoint returns is different for every provider, so a [`UserMapper`] turns its
+//! JSON into the principal of the hatch. [`Deserialized`] does so with serde:
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct GitHubUser { login: String, name: Option<String> }
+//!
+//! #[get("/")]
+//! fn index(user: Authenticated<OAuth2Hatch<Deserialized<GitHubUser>>>) -> String {
+//!     format!("Hello user: {}", user.login)
+//! }
+//!
+//! rocket::build().attach(Airlock::<OAuth2Hatch<Deserialized<GitHubUser>>>::fairing())
+//! ```
+//!
+//! Before the userinfo is handed to the mapper, the claims named in the `claims` table are copied to
+//! the standard `subject`, `preferred_username`, `name` and `email`, so [`OAuth2User`] can be used as principal
+//! for many providers. Without config, `subject` is copied from `sub`. The endpoints, scopes and claims of common
+//! providers can be filled in by a `provider` preset, see the [`provider`] module:
+//!
+//! ```toml
+//! [default.airlock.oauth2]
+//! provider = "github"
+//! client_id = "my-app"
+//! client_secret = "s3cr3t"
+//! ```
+//!
+//! The hatch mounts a single route `GET /login` at its base. Without `code` and `state`, it redirects to the
+//! authorization endpoint of the provider. With `code` and `state`, it is the callback the provider redirects
+//! back to, which exchanges the code, fetches the userinfo, stores the principal in a private cookie and
+//! redirects to the `return_to` query parameter the login was started with, or to `/`. That has to be a
+//! local path or an url on one of the `return_to_origins`, see [`ReturnTo`]. With `error`, the provider denied the login. Every instance of the hatch uses its own cookies. The PKCE
+//! code verifier of a login is kept in the [`StateStore`](crate::state::StateStore) until the callback.
+//!
+//! Only available with the `oauth2` feature.
+
+use std::{borrow::Cow, error::Error as StdError, fmt, marker::PhantomData};
+use ::oauth2::{
+    AccessToken, AuthorizationCode, AuthUrl, ClientId, ClientSecret, CsrfToken, HttpRequest, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
+    basic::BasicClient,
+    http::{header, HeaderMap, HeaderValue, Method},
+    reqwest::async_http_client,
+    url::Url,
+};
+use rocket::{
+    Build, Data, error_, info_, Request, Rocket, Route, warn_,
+    figment::{self, Figment},
+    http::{uri::Origin, CookieJar, Status},
+    response::Redirect,
+    route::{Handler, Outcome},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use yansi::Paint;
+use crate::{
+    Airlock, Communicator, flow, Hatch, Mounted, Result as HatchResult, registry, ReturnTo,
+    provider::{self, Claims, Flavor}, state::LoginStates,
+};
+
+
+/// Errors of the [`OAuth2Hatch`] and its [`OAuth2Client`].
+#[derive(Debug)]
+pub enum OAuth2Error {
+    /// The config in `airlock.oauth2` is missing or invalid.
+    Config(Box<figment::Error>),
+    /// The authorization code could not be exchanged for an access token.
+    TokenExchange(Box<dyn StdError + Send + Sync>),
+    /// The userinfo endpoint could not be asked or returned something that is not JSON.
+    UserInfo(Box<dyn StdError + Send + Sync>),
+    /// The [`UserMapper`] could not map the userinfo to a principal.
+    Mapping(Box<dyn StdError + Send + Sync>),
+    /// No [`OAuth2Client`] was connected to the hatch.
+    NotConnected,
+}
+
+impl fmt::Display for OAuth2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OAuth2Error::Config(e) => write!(f, "invalid OAuth2 config: {}", e),
+            OAuth2Error::TokenExchange(e) => write!(f, "exchanging the authorization code failed: {}", e),
+            OAuth2Error::UserInfo(e) => write!(f, "fetching the userinfo failed: {}", e),
+            OAuth2Error::Mapping(e) => write!(f, "mapping the userinfo to a principal failed: {}", e),
+            OAuth2Error::NotConnected => f.write_str("no OAuth2 client is connected to the hatch"),
+        }
+    }
+}
+
+impl StdError for OAuth2Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            OAuth2Error::Config(e) => Some(&**e),
+            OAuth2Error::TokenExchange(e) | OAuth2Error::UserInfo(e) | OAuth2Error::Mapping(e) => Some(&**e),
+            OAuth2Error::NotConnected => None,
+        }
+    }
+}
+
+impl From<figment::Error> for OAuth2Error {
+    fn from(e: figment::Error) -> Self {
+        OAuth2Error::Config(Box::new(e))
+    }
+}
+
+/// Turns the JSON returned by the userinfo endpoint into the principal of an [`OAuth2Hatch`].
+pub trait UserMapper: Send + Sync + 'static {
+    /// The principal, which is stored in a private cookie and therefore needs to be serializable.
+    type Principal: Serialize + DeserializeOwned + Send + Sync + 'static;
+
+    fn map_user(userinfo: serde_json::Value) -> Result<Self::Principal, Box<dyn StdError + Send + Sync>>;
+}
+
+/// Maps the userinfo by deserializing it into `T`.
+pub struct Deserialized<T>(PhantomData<fn() -> T>);
+
+impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> UserMapper for Deserialized<T> {
+    type Principal = T;
+
+    fn map_user(userinfo: serde_json::Value) -> Result<T, Box<dyn StdError + Send + Sync>> {
+        Ok(serde_json::from_value(userinfo)?)
+    }
+}
+
+/// The user that logged in, as stated by the standard claims the `claims` table picks from the userinfo.
+/// Use it as principal with `OAuth2Hatch<Deserialized<OAuth2User>>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuth2User {
+    pub subject: String,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OAuth2Config {
+    auth_url: String,
+    token_url: String,
+    userinfo_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+    #[serde(default = "default_pkce")]
+    pkce: bool,
+    #[serde(default)]
+    claims: Claims,
+    #[serde(default)]
+    return_to_origins: Vec<String>,
+}
+
+fn default_pkce() -> bool {
+    true
+}
+
+impl OAuth2Config {
+    #[allow(clippy::result_large_err)]
+    fn from(figment: &Figment) -> Result<Self, figment::Error> {
+        provider::extract(figment, &config_name(), Flavor::OAuth2)
+    }
+}
+
+/// The name of the config table of the hatch, which is the same for every [`UserMapper`].
+fn config_name() -> String {
+    registry::config_name::<OAuth2Hatch<Deserialized<()>>>()
+}
+
+fn invalid(key: &str, e: impl fmt::Display) -> figment::Error {
+    flow::invalid(&config_name(), key, e)
+}
+
+/// The communicator of the [`OAuth2Hatch`], which talks to the authorization and token endpoints.
+pub struct OAuth2Client(BasicClient);
+
+impl OAuth2Client {
+    /// The client of the [`oauth2`] crate, e.g. to use endpoints the hatch does not use itself.
+    pub fn inner(&self) -> &BasicClient {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl Communicator for OAuth2Client {
+    type Error = OAuth2Error;
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match OAuth2Config::from(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+        let auth_url = match AuthUrl::new(config.auth_url) {
+            Ok(url) => url,
+            Err(e) => return Err((rocket, invalid("auth_url", e).into())),
+        };
+        let token_url = match TokenUrl::new(config.token_url) {
+            Ok(url) => url,
+            Err(e) => return Err((rocket, invalid("token_url", e).into())),
+        };
+
+        info_!("Initializing OAuth2 Client");
+        let client = BasicClient::new(
+            ClientId::new(config.client_id),
+            config.client_secret.map(ClientSecret::new),
+            auth_url,
+            Some(token_url),
+        );
+        Ok((rocket, OAuth2Client(client)))
+    }
+}
+
+/// A login at the provider that was started, but not yet completed.
+pub struct Authorization {
+    /// The url of the authorization endpoint, to which the user is redirected.
+    pub url: Url,
+    pub state: CsrfToken,
+    /// The PKCE code verifier, unless PKCE is disabled.
+    pub pkce_verifier: Option<PkceCodeVerifier>,
+}
+
+/// Hatch that logs users in at a plain OAuth 2.0 provider. See the [module docs](self).
+pub struct OAuth2Hatch<M: UserMapper> {
+    client: Option<OAuth2Client>,
+    userinfo_url: Url,
+    scopes: Vec<Scope>,
+    pkce: bool,
+    /// Scheme and authority of rocket, to resolve relative redirect urls.
+    origin: Option<String>,
+    redirect_url: RedirectUrl,
+    configured_redirect: bool,
+    cookie_prefix: String,
+    claims: Claims,
+    return_to: ReturnTo,
+    states: LoginStates,
+    _mapper: PhantomData<fn() -> M>,
+}
+
+impl<M: UserMapper> OAuth2Hatch<M> {
+    fn client(&self) -> Result<&OAuth2Client, OAuth2Error> {
+        self.client.as_ref().ok_or(OAuth2Error::NotConnected)
+    }
+
+    fn cookie(&self, name: &str) -> String {
+        format!("{}_{}", self.cookie_prefix, name)
+    }
+
+    /// The url the provider redirects back to after the login.
+    pub fn redirect_url(&self) -> &RedirectUrl {
+        &self.redirect_url
+    }
+
+    /// The access token of the user that is logged in with the request, e.g. to call the API of the provider.
+    pub fn access_token(&self, request: &Request<'_>) -> Option<AccessToken> {
+        request.cookies()
+            .get_private(&self.cookie("token"))
+            .map(|token| AccessToken::new(token.value().to_string()))
+    }
+
+    /// Starts a login. Generates the url of the authorization endpoint of the provider, with a random
+    /// state and, unless disabled, a PKCE challenge.
+    pub fn authorize_url(&self) -> Result<Authorization, OAuth2Error> {
+        let mut request = self.client()?.0
+            .authorize_url(CsrfToken::new_random)
+            .add_scopes(self.scopes.iter().cloned())
+            .set_redirect_uri(Cow::Borrowed(&self.redirect_url));
+        let pkce_verifier = match self.pkce {
+            true => {
+                let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+                request = request.set_pkce_challenge(challenge);
+                Some(verifier)
+            },
+            false => None,
+        };
+        let (url, state) = request.url();
+
+        Ok(Authorization { url, state, pkce_verifier })
+    }
+
+    /// Exchanges the authorization code for an access token, with the `pkce_verifier` of the login, if it
+    /// used PKCE. Then fetches the userinfo with the access token and maps it to the principal.
+    pub async fn exchange_code(&self, code: String, pkce_verifier: Option<PkceCodeVerifier>) -> Result<(M::Principal, AccessToken), OAuth2Error> {
+        let mut request = self.client()?.0
+            .exchange_code(AuthorizationCode::new(code))
+            .set_redirect_uri(Cow::Borrowed(&self.redirect_url));
+        if let Some(verifier) = pkce_verifier {
+            request = request.set_pkce_verifier(verifier);
+        }
+        let response = request
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| OAuth2Error::TokenExchange(Box::new(e)))?;
+
+        let mut userinfo = self.userinfo(response.access_token()).await?;
+        let mapped = self.claims.map(&userinfo);
+        if let Some(userinfo) = userinfo.as_object_mut() {
+            userinfo.extend(mapped);
+        }
+        let principal = M::map_user(userinfo).map_err(OAuth2Error::Mapping)?;
+        Ok((principal, response.access_token().clone()))
+    }
+
+    /// Asks the userinfo endpoint who the owner of the access token is.
+    pub async fn userinfo(&self, access_token: &AccessToken) -> Result<serde_json::Value, OAuth2Error> {
+        let bearer = HeaderValue::from_str(&format!("Bearer {}", access_token.secret()))
+            .map_err(|e| OAuth2Error::UserInfo(Box::new(e)))?;
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, bearer);
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+        // Some providers, e.g. GitHub, reject requests without a user agent.
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("rocket_airlock"));
+
+        let request = HttpRequest { url: self.userinfo_url.clone(), method: Method::GET, headers, body: Vec::new() };
+        let response = async_http_client(request).await
+            .map_err(|e| OAuth2Error::UserInfo(Box::new(e)))?;
+        if !response.status_code.is_success() {
+            return Err(OAuth2Error::UserInfo(format!("userinfo endpoint answered with {}", response.status_code).into()));
+        }
+
+        serde_json::from_slice(&response.body).map_err(|e| OAuth2Error::UserInfo(Box::new(e)))
+    }
+
+    async fn login(&self, return_to: Option<String>, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+        let return_to = flow::return_to(&self.return_to, return_to)?;
+        let authorization = self.authorize_url().map_err(|e| {
+            error_!("{}", e);
+            Status::InternalServerError
+        })?;
+        flow::begin(
+            &self.states,
+            cookies,
+            &self.cookie_prefix,
+            authorization.state.secret(),
+            None,
+            authorization.pkce_verifier.as_ref().map(|verifier| verifier.secret().to_string()),
+            return_to,
+        ).await?;
+
+        info_!("Redirecting to {}", Paint::new(authorization.url.as_str()).underline());
+        Ok(Redirect::to(authorization.url.to_string()))
+    }
+
+    async fn callback(&self, code: String, state: String, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+        let login = flow::complete(&self.states, cookies, &self.cookie_prefix, &state).await?;
+        let pkce_verifier = login.pkce_verifier.map(PkceCodeVerifier::new);
+        if self.pkce && pkce_verifier.is_none() {
+            warn_!("The PKCE code verifier of the login flow is missing.");
+            return Err(Status::BadRequest);
+        }
+
+        let (principal, access_token) = self.exchange_code(code, pkce_verifier).await
+            .map_err(|e| {
+                error_!("{}", e);
+                match e {
+                    OAuth2Error::Mapping(_) => Status::Unauthorized,
+                    _ => Status::BadGateway,
+                }
+            })?;
+
+        let session = serde_json::to_string(&principal).map_err(|e| {
+            error_!("Could not store OAuth2 session: {}", e);
+            Status::InternalServerError
+        })?;
+        cookies.add_private(flow::cookie(self.cookie("user"), session));
+        cookies.add_private(flow::cookie(self.cookie("token"), access_token.secret().to_string()));
+
+        info_!("User logged in with OAuth2");
+        Ok(Redirect::to(login.return_to.unwrap_or_else(|| "/".to_string())))
+    }
+}
+
+#[rocket::async_trait]
+impl<M: UserMapper> Hatch for OAuth2Hatch<M> {
+    type Comm = OAuth2Client;
+    type Error = OAuth2Error;
+    type Principal = M::Principal;
+
+    fn comm(&self) -> &OAuth2Client {
+        self.client.as_ref().expect("Communicator should have been connected")
+    }
+
+    fn connect_comm(&mut self, comm: Self::Comm) {
+        self.client = Some(comm);
+    }
+
+    fn name() -> &'static str {
+        "OAuth2"
+    }
+
+    fn routes() -> Vec<Route> {
+        vec![Route::new(rocket::http::Method::Get, "/login", Login::<M>(PhantomData))]
+    }
+
+    fn login_uri(&self) -> Option<Origin<'static>> {
+        Some(Origin::parse("/login").expect("Valid login uri"))
+    }
+
+    fn docked(&mut self, name: &str, base: &Origin<'static>) {
+        self.cookie_prefix = format!("airlock_{}", name);
+        if let (false, Some(origin)) = (self.configured_redirect, &self.origin) {
+            match RedirectUrl::new(flow::login_url(origin, base)) {
+                Ok(url) => self.redirect_url = url,
+                Err(e) => warn_!("Could not derive redirect url from mount base `{}`: {}", base, e),
+            }
+        }
+        info_!("Redirect url: {}", Paint::new(self.redirect_url.as_str()).underline());
+    }
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let figment = rocket.figment();
+        let config = match OAuth2Config::from(figment) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+        let origin = match flow::origin(figment) {
+            Ok(origin) => origin,
+            Err(e) => return Err((rocket, e.into())),
+        };
+        let userinfo_url = match Url::parse(&config.userinfo_url) {
+            Ok(url) => url,
+            Err(e) => return Err((rocket, invalid("userinfo_url", e).into())),
+        };
+
+        let redirect_url = match config.redirect_url.as_deref() {
+            Some(url) => flow::absolute(origin.as_deref(), url),
+            None => origin.as_ref().map(|origin| format!("{}/login", origin)),
+        };
+        let redirect_url = match redirect_url.map(RedirectUrl::new) {
+            Some(Ok(url)) => url,
+            Some(Err(e)) => return Err((rocket, invalid("redirect_url", e).into())),
+            None => return Err((rocket, invalid("redirect_url", flow::UNKNOWN_ORIGIN).into())),
+        };
+        let return_to = match flow::return_to_origins(&config_name(), origin.as_deref(), &config.return_to_origins) {
+            Ok(return_to) => return_to,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        let hatch = OAuth2Hatch {
+            client: None,
+            userinfo_url,
+            scopes: config.scopes.into_iter().map(Scope::new).collect(),
+            pkce: config.pkce,
+            origin,
+            redirect_url,
+            configured_redirect: config.redirect_url.is_some(),
+            cookie_prefix: format!("airlock_{}", config_name()),
+            claims: config.claims,
+            return_to,
+            states: LoginStates::of(&rocket),
+            _mapper: PhantomData,
+        };
+        Ok((rocket, hatch))
+    }
+
+    async fn authenticate(&self, request: &Request<'_>) -> Option<M::Principal> {
+        let user = request.cookies().get_private(&self.cookie("user"))?;
+        match serde_json::from_str(user.value()) {
+            Ok(principal) => Some(principal),
+            Err(e) => {
+                warn_!("Discarding invalid OAuth2 session: {}", e);
+                None
+            },
+        }
+    }
+}
+
+/// Handler of the login route. Route attributes cannot be generic over the [`UserMapper`], so it
+/// decides by the query parameters whether to start a login or to complete it.
+struct Login<M>(PhantomData<fn() -> M>);
+
+impl<M> Clone for Login<M> {
+    fn clone(&self) -> Self {
+        Login(PhantomData)
+    }
+}
+
+#[rocket::async_trait]
+impl<M: UserMapper> Handler for Login<M> {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let airlock = match request.guard::<Airlock<OAuth2Hatch<M>, Mounted>>().await.succeeded() {
+            Some(airlock) => airlock,
+            None => return Outcome::forward(data, Status::InternalServerError),
+        };
+        let query = |name: &str| request.query_value::<String>(name).and_then(Result::ok);
+
+        let result = match (query("error"), query("code"), query("state")) {
+            (Some(error), _, _) => {
+                warn_!("OAuth2 provider denied the login: {} {}", error, query("error_description").unwrap_or_default());
+                Err(Status::Unauthorized)
+            },
+            (None, Some(code), Some(state)) => airlock.hatch.callback(code, state, request.cookies()).await,
+            _ => airlock.hatch.login(query(crate::RETURN_TO), request.cookies()).await,
+        };
+
+        match result {
+            Ok(redirect) => Outcome::from(request, redirect),
+            Err(status) => Outcome::error(status),
+        }
+    }
+}
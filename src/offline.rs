@@ -0,0 +1,49 @@
+//! Keeping the last good answer of a provider on disk, so rocket can ignite while the provider is down.
+
+use std::{path::{Path, PathBuf}, time::Duration};
+use rocket::{tokio::fs, warn_};
+use serde::{de::DeserializeOwned, Serialize};
+
+
+/// How often a provider is tried again, if rocket was started with the cached answer and no refresh
+/// interval is configured.
+pub(crate) const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Stores `value` as JSON at `path`. A failure is only logged, as the cache is a fallback.
+pub(crate) async fn save<T: Serialize>(path: &Path, value: &T) {
+    let json = match serde_json::to_vec(value) {
+        Ok(json) => json,
+        Err(e) => return warn_!("Could not serialize cache `{}`: {}", path.display(), e),
+    };
+    // Written next to the cache and renamed, so a crash never leaves a truncated cache behind.
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+    let written = async {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).await?;
+        }
+        fs::write(&tmp, json).await?;
+        fs::rename(&tmp, path).await
+    };
+    if let Err(e) = written.await {
+        warn_!("Could not write cache `{}`: {}", path.display(), e);
+    }
+}
+
+/// Loads the value stored at `path`, if there is a readable one.
+pub(crate) async fn restore<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let json = match fs::read(path).await {
+        Ok(json) => json,
+        Err(e) => {
+            warn_!("Could not read cache `{}`: {}", path.display(), e);
+            return None;
+        },
+    };
+    match serde_json::from_slice(&json) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn_!("Discarding invalid cache `{}`: {}", path.display(), e);
+            None
+        },
+    }
+}
@@ -0,0 +1,1241 @@
+//! OpenID Connect relying party. The [`OidcHatch`] logs users in with the authorization code flow of an
+//! OpenID Provider, which is discovered from the `airlock.openidconnect` table of the rocket config, e.g.:
+//!
+//! ```toml
+//! [default.airlock.openidconnect]
+//! discover_url = "http://localhost:8080/realms/demo"
+//! client_id = "my-app"
+//! client_secret = "s3cr3t"
+//! # Optional, defaults to the login route of the hatch, see below.
+//! redirect_url = "https://app.example.com/login"
+//! # Optional, `openid` is always requested.
+//! scopes = ["profile", "email"]
+//! # Optional, PKCE with S256 is used unless disabled.
+//! pkce = true
+//! # Optional, seconds after which the provider is discovered again, e.g. to pick up new keys.
+//! refresh_interval = 3600
+//! # Optional, where the last discovered metadata is stored, to start with it if the provider is unreachable.
+//! cache = "cache/openidconnect.json"
+//! # Optional, the urls the provider may redirect to after a logout. The first one is the default.
+//! post_logout_redirect_urls = ["/", "https://app.example.com/goodbye"]
+//! # Optional, other origins a login may return to, besides local paths.
+//! return_to_origins = ["https://portal.example.com"]
+//! # Optional, enable the back-channel and front-channel logout routes and server-side sessions, see below.
+//! backchannel_logout = false
+//! frontchannel_logout = false
+//! # Optional, the seconds between two checks of the session with Session Management.
+//! check_session_interval = 5
+//! # Optional, the seconds after which a server-side session ends.
+//! session_lifetime = 86400
+//! # Optional, whether access tokens are refreshed with the refresh token of the login.
+//! refresh_tokens = true
+//! # Optional, the seconds before its expiry an access token is refreshed.
+//! token_refresh_margin = 30
+//! ```
+//!
+//! The hatch mounts its routes at the base of the hatch, see [`HatchFairing`](crate::HatchFairing):
+//!
+//! * `GET /login?<return_to>` redirects to the authorization endpoint of the provider.
+//! * `GET /login?<code>&<state>` is the callback the provider redirects back to. It exchanges the code,
+//!   verifies the ID token, stores the claims of the user in a private cookie and redirects to the
+//!   `return_to` of the login, or to `/`. [`RedirectToLogin`](crate::RedirectToLogin) passes the uri of
+//!   the request that needed the login as `return_to`, which has to be a local path or an url on one of
+//!   the `return_to_origins`, see [`ReturnTo`].
+//! * `GET /login?<error>` is the callback, if the provider denied the login.
+//! * `GET /logout?<redirect>` removes all cookies of airlock and redirects to the end session endpoint of
+//!   the provider, with the ID token of the login as hint. The provider then redirects to `redirect`, which
+//!   has to be one of the `post_logout_redirect_urls`, or to the first of them. If the provider does not
+//!   support RP-initiated logout, the route redirects there itself.
+//! * `POST /backchannel-logout` receives the logout token of an OpenID Connect Back-Channel Logout from
+//!   the provider, and ends the sessions it names by `sid` or `sub`.
+//! * `GET /frontchannel-logout?<iss>&<sid>` is the page of an OpenID Connect Front-Channel Logout, which
+//!   the provider embeds in an iframe. It ends the sessions with `sid` and removes all cookies of airlock.
+//! * `GET /check-session` is the RP iframe of OpenID Connect Session Management, see below.
+//!
+//! If the provider issues a refresh token, it is kept in a private cookie, and the access token is refreshed
+//! when a request is authenticated within `token_refresh_margin` seconds before it expires. The tokens the
+//! provider issues with the refresh replace the stored ones, even if the request ends with an error, and
+//! concurrent requests of a session share a single refresh. If the refresh fails, or the refreshed ID token
+//! is issued for another `sub`, the cookies of the hatch are removed, so the user has to log in again.
+//!
+//! The nonce and PKCE code verifier of a login are kept in the [`StateStore`](crate::state::StateStore)
+//! for the round-trip to the provider, under the `state` of the login. PKCE protects the authorization code of public clients without a `client_secret`
+//! and is recommended by OAuth 2.1 for all clients, so only disable it if the provider does not support it.
+//!
+//! With `backchannel_logout` or `frontchannel_logout`, every login starts a server-side session in the [`Sessions`] of the hatch,
+//! which is indexed by the `sub` and `sid` of the ID token, and requests are only authenticated while
+//! their session lasts. The sessions are kept in memory, so users have to log in again after a restart.
+//! The url of the route, e.g. `https://app.example.com/backchannel-logout`, has to be registered as
+//! back-channel or front-channel logout url of the client at the provider. Browsers usually do not send
+//! cookies to the iframe of a front-channel logout, so the provider should be told to send `iss` and `sid`.
+//!
+//! If the provider supports Session Management, pages of the app can embed the RP iframe to notice when
+//! the user logs out at the provider, e.g. with `<iframe src="/check-session" hidden></iframe>`. It asks
+//! the OP iframe of the provider every `check_session_interval` seconds whether the `session_state` of the
+//! login changed, and if it did, navigates the page to the logout route.
+//!
+//! Instead of the `discover_url`, a `provider` preset can be named, see the [`provider`] module.
+//! Its `claims` table picks the claims of the ID token that make up the [`OidcUser`].
+//!
+//! A relative `redirect_url` or entry of `post_logout_redirect_urls` is resolved against the public url
+//! of rocket, a missing `redirect_url` defaults to the login route. The public url is `airlock.public_url`,
+//! e.g. `"https://app.example.com"`, or else the `address` and `port` of rocket. If rocket listens on an
+//! unspecified address like `0.0.0.0`, it is unknown and the urls need to be absolute. Requests are authenticated
+//! with the private cookie, so the principal is an [`OidcUser`]. Every instance of the hatch uses its own cookies.
+//!
+//! Only available with the `oidc` feature.
+
+use std::{
+    borrow::Cow, collections::HashMap, fmt, path::{Path, PathBuf}, str::FromStr, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use arc_swap::ArcSwap;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use openidconnect::{
+    AdditionalProviderMetadata, AuthenticationFlow, AuthorizationCode, ClaimsVerificationError, ClientId, ClientSecret, CsrfToken,
+    EndSessionUrl, IssuerUrl, JsonWebKey, JsonWebKeyId, LogoutProviderMetadata, LogoutRequest, Nonce, OAuth2TokenResponse,
+    PkceCodeChallenge, PkceCodeVerifier, PostLogoutRedirectUrl, RedirectUrl, RefreshToken, Scope,
+    SignatureVerificationError, TokenResponse,
+    core::{
+        CoreAuthDisplay, CoreClaimName, CoreClaimType, CoreClient, CoreClientAuthMethod, CoreGrantType,
+        CoreIdToken, CoreIdTokenClaims, CoreJsonWebKey, CoreJsonWebKeySet, CoreJsonWebKeyType, CoreJsonWebKeyUse,
+        CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm, CoreJwsSigningAlgorithm,
+        CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType, CoreTokenResponse,
+    },
+    reqwest::async_http_client,
+    url::Url,
+};
+use rocket::{
+    Build, error_, FromForm, get, info_, post, Request, Responder, Rocket, Route, routes, warn_,
+    figment::{self, Figment},
+    form::Form,
+    http::{uri::Origin, Cookie, CookieJar, Header, Status},
+    response::{status::Custom, Redirect},
+    tokio::sync::OnceCell,
+};
+use serde::{Deserialize, Serialize};
+use yansi::Paint;
+use crate::{
+    Airlock, Communicator, flow, Hatch, Mounted, offline, Result as HatchResult, registry, ReturnTo,
+    authenticated::{AddedCookies, RemovedCookies}, provider::{self, Claims, Flavor}, rotation::Rotation, session::Sessions, state::LoginStates,
+};
+
+
+/// Errors of the [`OidcHatch`] and its [`OidcClient`].
+#[derive(Debug)]
+pub enum OidcError {
+    /// The config in `airlock.openidconnect` is missing or invalid.
+    Config(Box<figment::Error>),
+    /// The discovery document of the provider could not be fetched or is invalid.
+    Discovery(Box<dyn std::error::Error + Send + Sync>),
+    /// The authorization code could not be exchanged for tokens.
+    TokenExchange(Box<dyn std::error::Error + Send + Sync>),
+    /// The provider did not return an ID token, so it seems to only speak OAuth 2.0.
+    MissingIdToken,
+    /// The ID token returned by the provider did not pass verification.
+    InvalidIdToken(ClaimsVerificationError),
+    /// The access token could not be refreshed with the refresh token.
+    TokenRefresh(Box<dyn std::error::Error + Send + Sync>),
+    /// The url to redirect to after a logout is not one of the `post_logout_redirect_urls`.
+    LogoutRedirect(String),
+    /// The logout token of a back-channel logout did not pass verification.
+    InvalidLogoutToken(String),
+    /// No [`OidcClient`] was connected to the hatch.
+    NotConnected,
+}
+
+impl fmt::Display for OidcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OidcError::Config(e) => write!(f, "invalid OpenID Connect config: {}", e),
+            OidcError::Discovery(e) => write!(f, "OpenID Provider discovery failed: {}", e),
+            OidcError::TokenExchange(e) => write!(f, "exchanging the authorization code failed: {}", e),
+            OidcError::MissingIdToken => f.write_str("no ID token found, the provider seems to only speak OAuth 2.0"),
+            OidcError::InvalidIdToken(e) => write!(f, "invalid ID token: {}", e),
+            OidcError::TokenRefresh(e) => write!(f, "refreshing the access token failed: {}", e),
+            OidcError::LogoutRedirect(url) => write!(f, "`{}` is not one of the `post_logout_redirect_urls`", url),
+            OidcError::InvalidLogoutToken(e) => write!(f, "invalid logout token: {}", e),
+            OidcError::NotConnected => f.write_str("no OpenID Connect client is connected to the hatch"),
+        }
+    }
+}
+
+impl std::error::Error for OidcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OidcError::Config(e) => Some(&**e),
+            OidcError::Discovery(e) | OidcError::TokenExchange(e) | OidcError::TokenRefresh(e) => Some(&**e),
+            OidcError::InvalidIdToken(e) => Some(e),
+            OidcError::MissingIdToken | OidcError::LogoutRedirect(_) | OidcError::InvalidLogoutToken(_)
+                | OidcError::NotConnected => None,
+        }
+    }
+}
+
+impl From<figment::Error> for OidcError {
+    fn from(e: figment::Error) -> Self {
+        OidcError::Config(Box::new(e))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcConfig {
+    discover_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: Option<String>,
+    #[serde(default = "default_scopes")]
+    scopes: Vec<String>,
+    #[serde(default = "default_pkce")]
+    pkce: bool,
+    #[serde(default)]
+    claims: Claims,
+    refresh_interval: Option<u64>,
+    cache: Option<PathBuf>,
+    #[serde(default)]
+    post_logout_redirect_urls: Vec<String>,
+    #[serde(default)]
+    return_to_origins: Vec<String>,
+    #[serde(default)]
+    backchannel_logout: bool,
+    #[serde(default)]
+    frontchannel_logout: bool,
+    #[serde(default = "default_check_session_interval")]
+    check_session_interval: u64,
+    #[serde(default = "default_refresh_tokens")]
+    refresh_tokens: bool,
+    #[serde(default = "default_token_refresh_margin")]
+    token_refresh_margin: u64,
+    #[serde(default = "default_session_lifetime")]
+    session_lifetime: u64,
+}
+
+fn default_scopes() -> Vec<String> {
+    vec!["profile".into(), "email".into()]
+}
+
+fn default_pkce() -> bool {
+    true
+}
+
+fn default_session_lifetime() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_check_session_interval() -> u64 {
+    5
+}
+
+fn default_refresh_tokens() -> bool {
+    true
+}
+
+fn default_token_refresh_margin() -> u64 {
+    30
+}
+
+impl OidcConfig {
+    #[allow(clippy::result_large_err)]
+    fn from(figment: &Figment) -> Result<Self, figment::Error> {
+        provider::extract(figment, &registry::config_name::<OidcHatch>(), Flavor::Oidc)
+    }
+}
+
+/// The communicator of the [`OidcHatch`]. It holds the metadata of the OpenID Provider, which is
+/// discovered when the hatch is installed. The metadata, including the keys of the provider, is discovered
+/// again every `refresh_interval` seconds, if configured, and whenever an ID token is signed with a key
+/// that is not known yet. It is swapped atomically, so requests are never blocked by a refresh.
+///
+/// With a `cache` path, every discovered metadata and JWKS is stored there. If the provider is unreachable
+/// when the hatch is installed, the stored metadata is used instead and the provider is discovered again
+/// in the background.
+pub struct OidcClient {
+    metadata: ArcSwap<Metadata>,
+    issuer_url: IssuerUrl,
+    client_id: ClientId,
+    client_secret: Option<ClientSecret>,
+    cache: Option<PathBuf>,
+    refresh_interval: Option<Duration>,
+    /// Whether the metadata was restored from the cache and not discovered since.
+    restored: AtomicBool,
+    rotation: Rotation,
+}
+
+/// The metadata of Session Management 1.0, which the [`openidconnect`] crate does not know.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionManagementMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    check_session_iframe: Option<Url>,
+}
+
+impl AdditionalProviderMetadata for SessionManagementMetadata {}
+
+/// The discovery document of a provider, with the metadata of RP-initiated logout and Session Management.
+type ProviderMetadata = openidconnect::ProviderMetadata<
+    LogoutProviderMetadata<SessionManagementMetadata>,
+    CoreAuthDisplay, CoreClientAuthMethod, CoreClaimName, CoreClaimType, CoreGrantType,
+    CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm, CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType, CoreJsonWebKeyUse, CoreJsonWebKey, CoreResponseMode, CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
+/// Everything the hatch uses of the discovered metadata. It is swapped as a whole, when the provider is
+/// discovered again.
+struct Metadata {
+    client: Arc<CoreClient>,
+    jwks: CoreJsonWebKeySet,
+    end_session_url: Option<EndSessionUrl>,
+    check_session_iframe: Option<Url>,
+}
+
+impl Metadata {
+    fn new(metadata: ProviderMetadata, client_id: &ClientId, client_secret: &Option<ClientSecret>) -> Self {
+        let logout = metadata.additional_metadata();
+        let end_session_url = logout.end_session_endpoint.clone();
+        let check_session_iframe = logout.additional_metadata.check_session_iframe.clone();
+        let jwks = metadata.jwks().clone();
+        let client = CoreClient::from_provider_metadata(metadata, client_id.clone(), client_secret.clone());
+        Metadata { client: Arc::new(client), jwks, end_session_url, check_session_iframe }
+    }
+}
+
+/// The metadata of a provider as it is cached. The JWKS is not part of the discovery document, so it
+/// is stored next to it.
+#[derive(Serialize, Deserialize)]
+struct Discovered {
+    metadata: ProviderMetadata,
+    jwks: CoreJsonWebKeySet,
+}
+
+impl OidcClient {
+    /// Discovers the OpenID Provider at `issuer_url`.
+    pub async fn discover(issuer_url: IssuerUrl, client_id: ClientId, client_secret: Option<ClientSecret>) -> Result<Self, OidcError> {
+        let metadata = Self::metadata(&issuer_url).await?;
+        Ok(OidcClient::with_metadata(metadata, issuer_url, client_id, client_secret))
+    }
+
+    /// Discovers the OpenID Provider at `issuer_url` and stores its metadata at `cache`. If that fails, the
+    /// metadata that was stored at `cache` before is used and the provider is discovered again every
+    /// `refresh_interval`, or every 5 minutes until it was discovered.
+    pub async fn discover_or_restore(issuer_url: IssuerUrl, client_id: ClientId, client_secret: Option<ClientSecret>, cache: PathBuf) -> Result<Self, OidcError> {
+        let mut client = match Self::metadata(&issuer_url).await {
+            Ok(metadata) => {
+                Self::save(&cache, &metadata).await;
+                OidcClient::with_metadata(metadata, issuer_url, client_id, client_secret)
+            },
+            Err(e) => {
+                let Some(discovered) = offline::restore::<Discovered>(&cache).await else {
+                    return Err(e);
+                };
+                warn_!("{}", e);
+                warn_!("Using OpenID Provider metadata from cache `{}` until {} is reachable", cache.display(), issuer_url.as_str());
+                let metadata = discovered.metadata.set_jwks(discovered.jwks);
+                let client = OidcClient::with_metadata(metadata, issuer_url, client_id, client_secret);
+                client.restored.store(true, Ordering::Relaxed);
+                client
+            },
+        };
+        client.cache = Some(cache);
+        Ok(client)
+    }
+
+    fn with_metadata(metadata: ProviderMetadata, issuer_url: IssuerUrl, client_id: ClientId, client_secret: Option<ClientSecret>) -> Self {
+        OidcClient {
+            metadata: ArcSwap::from_pointee(Metadata::new(metadata, &client_id, &client_secret)),
+            issuer_url,
+            client_id,
+            client_secret,
+            cache: None,
+            refresh_interval: None,
+            restored: AtomicBool::new(false),
+            rotation: Rotation::default(),
+        }
+    }
+
+    async fn metadata(issuer_url: &IssuerUrl) -> Result<ProviderMetadata, OidcError> {
+        ProviderMetadata::discover_async(issuer_url.clone(), async_http_client).await
+            .map_err(|e| OidcError::Discovery(Box::new(e)))
+    }
+
+    async fn save(cache: &Path, metadata: &ProviderMetadata) {
+        let discovered = Discovered { metadata: metadata.clone(), jwks: metadata.jwks().clone() };
+        offline::save(cache, &discovered).await;
+    }
+
+    /// Discovers the provider again every `interval`, while rocket is in orbit.
+    pub fn refresh_every(mut self, interval: Duration) -> Self {
+        self.refresh_interval = Some(interval);
+        self
+    }
+
+    /// The client of the [`openidconnect`] crate, e.g. to use endpoints the hatch does not use itself.
+    pub fn inner(&self) -> Arc<CoreClient> {
+        self.metadata.load().client.clone()
+    }
+
+    /// The end session endpoint of the provider, if it supports RP-initiated logout.
+    pub fn end_session_url(&self) -> Option<EndSessionUrl> {
+        self.metadata.load().end_session_url.clone()
+    }
+
+    /// The url of the OP iframe of the provider, if it supports Session Management.
+    pub fn check_session_iframe(&self) -> Option<Url> {
+        self.metadata.load().check_session_iframe.clone()
+    }
+
+    /// Verifies the logout token of a back-channel logout, as specified by OpenID Connect Back-Channel
+    /// Logout 1.0. It has to be signed by the provider and issued for this client, and has to name a
+    /// session with `sub` or `sid`.
+    pub async fn verify_logout_token(&self, token: &str) -> Result<LogoutToken, OidcError> {
+        let invalid = |e: &dyn fmt::Display| OidcError::InvalidLogoutToken(e.to_string());
+        let Some(((header, payload), (message, signature))) = token.rsplit_once('.')
+            .and_then(|(message, signature)| Some((message.split_once('.')?, (message, signature))))
+        else {
+            return Err(invalid(&"not a signed JWT"));
+        };
+        let header: JwtHeader = decode(header).ok_or_else(|| invalid(&"invalid header"))?;
+        let claims: serde_json::Value = decode(payload).ok_or_else(|| invalid(&"invalid claims"))?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|e| invalid(&e))?;
+
+        match self.verify_signature(&header, message.as_bytes(), &signature) {
+            Err(SignatureVerificationError::NoMatchingKey) if self.rotate().await => {
+                self.verify_signature(&header, message.as_bytes(), &signature)
+            },
+            result => result,
+        }.map_err(|e| invalid(&e))?;
+
+        let claim = |name: &str| claims.get(name).filter(|value| !value.is_null());
+        if claim("iss").and_then(|iss| iss.as_str()) != Some(self.issuer_url.as_str()) {
+            return Err(invalid(&"`iss` is not the issuer of the provider"));
+        }
+        let audiences = match claim("aud") {
+            Some(serde_json::Value::Array(audiences)) => audiences.iter().filter_map(|aud| aud.as_str()).collect(),
+            Some(serde_json::Value::String(audience)) => vec![audience.as_str()],
+            _ => Vec::new(),
+        };
+        if !audiences.contains(&self.client_id.as_str()) {
+            return Err(invalid(&"`aud` does not contain the client id"));
+        }
+        if claim("iat").and_then(|iat| iat.as_u64()).is_none() {
+            return Err(invalid(&"`iat` is missing"));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        match claim("exp").and_then(|exp| exp.as_u64()) {
+            Some(exp) if exp + LOGOUT_TOKEN_LEEWAY.as_secs() >= now => {},
+            Some(_) => return Err(invalid(&"the token expired")),
+            None => return Err(invalid(&"`exp` is missing")),
+        }
+        if claim("events").and_then(|events| events.get(BACKCHANNEL_LOGOUT_EVENT)).is_none() {
+            return Err(invalid(&"`events` does not contain the back-channel logout event"));
+        }
+        if claim("nonce").is_some() {
+            return Err(invalid(&"a logout token must not contain a `nonce`"));
+        }
+
+        let logout = LogoutToken {
+            subject: claim("sub").and_then(|sub| sub.as_str()).map(str::to_string),
+            sid: claim("sid").and_then(|sid| sid.as_str()).map(str::to_string),
+        };
+        if logout.subject.is_none() && logout.sid.is_none() {
+            return Err(invalid(&"neither `sub` nor `sid` is present"));
+        }
+        Ok(logout)
+    }
+
+    /// Verifies the signature of a JWT with the keys of the provider, or the client secret for HMAC.
+    fn verify_signature(&self, header: &JwtHeader, message: &[u8], signature: &[u8]) -> Result<(), SignatureVerificationError> {
+        use CoreJwsSigningAlgorithm::*;
+        match &header.alg {
+            None => Err(SignatureVerificationError::DisallowedAlg("none".into())),
+            HmacSha256 | HmacSha384 | HmacSha512 => {
+                let secret = self.client_secret.as_ref().ok_or(SignatureVerificationError::NoMatchingKey)?;
+                CoreJsonWebKey::new_symmetric(secret.secret().as_bytes().to_vec())
+                    .verify_signature(&header.alg, message, signature)
+            },
+            alg => {
+                let metadata = self.metadata.load();
+                let mut keys = metadata.jwks.keys().iter()
+                    .filter(|key| header.kid.as_ref().is_none_or(|kid| key.key_id() == Some(kid)))
+                    .filter(|key| key.key_use().is_none_or(|key_use| *key_use == CoreJsonWebKeyUse::Signature))
+                    .peekable();
+                if keys.peek().is_none() {
+                    return Err(SignatureVerificationError::NoMatchingKey);
+                }
+                keys.map(|key| key.verify_signature(alg, message, signature))
+                    .find(Result::is_ok)
+                    .unwrap_or(Err(SignatureVerificationError::CryptoError("no key verified the signature".into())))
+            },
+        }
+    }
+
+    /// Discovers the provider again, because an ID token was signed with an unknown key. Returns whether
+    /// it was discovered again, which it is not, if it was discovered just before.
+    async fn rotate(&self) -> bool {
+        self.rotation.rotate(async {
+            match self.refresh().await {
+                Ok(()) => true,
+                Err(e) => {
+                    warn_!("{}", e);
+                    false
+                },
+            }
+        }).await
+    }
+}
+
+#[rocket::async_trait]
+impl Communicator for OidcClient {
+    type Error = OidcError;
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let config = match OidcConfig::from(rocket.figment()) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+        let issuer_url = match IssuerUrl::new(config.discover_url.clone()) {
+            Ok(url) => url,
+            Err(e) => return Err((rocket, invalid("discover_url", e).into())),
+        };
+
+        info_!("Fetching OpenID Connect discover manifest at: {}", Paint::new(&config.discover_url).underline());
+        let client_id = ClientId::new(config.client_id);
+        let client_secret = config.client_secret.map(ClientSecret::new);
+        let client = match config.cache {
+            Some(cache) => OidcClient::discover_or_restore(issuer_url, client_id, client_secret, cache).await,
+            None => OidcClient::discover(issuer_url, client_id, client_secret).await,
+        };
+        match client {
+            Ok(mut client) => {
+                info_!("Initializing OpenID Client");
+                if let Some(interval) = config.refresh_interval {
+                    client.refresh_interval = Some(Duration::from_secs(interval));
+                }
+                Ok((rocket, client))
+            },
+            Err(e) => Err((rocket, e)),
+        }
+    }
+
+    fn refresh_interval(&self) -> Option<Duration> {
+        match self.refresh_interval {
+            Some(interval) => Some(interval),
+            None if self.restored.load(Ordering::Relaxed) => Some(offline::RETRY_INTERVAL),
+            None => None,
+        }
+    }
+
+    async fn refresh(&self) -> Result<(), Self::Error> {
+        let metadata = Self::metadata(&self.issuer_url).await?;
+        if let Some(cache) = &self.cache {
+            Self::save(cache, &metadata).await;
+        }
+        let metadata = Metadata::new(metadata, &self.client_id, &self.client_secret);
+        info_!("Refreshed OpenID Connect discover manifest of {}", self.issuer_url.as_str());
+        self.metadata.store(Arc::new(metadata));
+        self.restored.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// The user that logged in, as stated by the claims of the ID token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcUser {
+    /// The `sub` claim, which identifies the user at the provider.
+    pub subject: String,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// The access token that was issued together with the ID token.
+    #[serde(skip)]
+    pub access_token: Option<String>,
+}
+
+impl From<&CoreIdTokenClaims> for OidcUser {
+    fn from(claims: &CoreIdTokenClaims) -> Self {
+        OidcUser {
+            subject: claims.subject().to_string(),
+            preferred_username: claims.preferred_username().map(|username| username.to_string()),
+            name: claims.name().and_then(|name| name.get(None)).map(|name| name.to_string()),
+            email: claims.email().map(|email| email.to_string()),
+            access_token: None,
+        }
+    }
+}
+
+/// A login at the provider that was started, but not yet completed.
+pub struct Authorization {
+    /// The url of the authorization endpoint, to which the user is redirected.
+    pub url: Url,
+    pub state: CsrfToken,
+    pub nonce: Nonce,
+    /// The PKCE code verifier, unless PKCE is disabled.
+    pub pkce_verifier: Option<PkceCodeVerifier>,
+}
+
+/// How long the result of a refresh is handed to requests that still carry the old refresh token, e.g.
+/// because they were sent before the browser received the new one.
+const REFRESH_REUSE: Duration = Duration::from_secs(60);
+
+/// The result of a refresh, the user is only present if the provider issued a new ID token.
+type Refreshed = Option<(Option<OidcUser>, CoreTokenResponse)>;
+
+/// The refreshes of access tokens that run or ran within [`REFRESH_REUSE`], by refresh token. Concurrent
+/// requests of a session share a single refresh, so a refresh token that may only be used once is
+/// not used twice.
+#[derive(Default)]
+struct Refreshes(Mutex<HashMap<String, Refresh>>);
+
+struct Refresh {
+    started: Instant,
+    result: Arc<OnceCell<Refreshed>>,
+}
+
+impl Refreshes {
+    /// Runs `refresh` for `refresh_token`, unless it runs or ran already, in which case its result is shared.
+    async fn run(&self, refresh_token: &str, refresh: impl std::future::Future<Output = Refreshed>) -> Refreshed {
+        let cell = {
+            let mut refreshes = self.0.lock().expect("Refreshes are not poisoned");
+            refreshes.retain(|_, refresh| refresh.started.elapsed() < REFRESH_REUSE);
+            refreshes.entry(refresh_token.to_string())
+                .or_insert_with(|| Refresh { started: Instant::now(), result: Arc::new(OnceCell::new()) })
+                .result.clone()
+        };
+        cell.get_or_init(|| refresh).await.clone()
+    }
+}
+
+/// The session a back-channel logout ends, as named by a verified logout token.
+#[derive(Debug, Clone)]
+pub struct LogoutToken {
+    /// The `sub` claim of the user.
+    pub subject: Option<String>,
+    /// The `sid` claim of the session at the provider.
+    pub sid: Option<String>,
+}
+
+/// The member of the `events` claim, that makes a JWT a logout token.
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// How long after its `exp` a logout token is still accepted, as the clocks of rocket and the provider may differ.
+const LOGOUT_TOKEN_LEEWAY: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: CoreJwsSigningAlgorithm,
+    kid: Option<JsonWebKeyId>,
+}
+
+/// Decodes a base64url encoded part of a JWT from JSON.
+fn decode<T: serde::de::DeserializeOwned>(part: &str) -> Option<T> {
+    let json = URL_SAFE_NO_PAD.decode(part).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Hatch that logs users in at an OpenID Provider. See the [module docs](self).
+pub struct OidcHatch {
+    client: Option<OidcClient>,
+    scopes: Vec<Scope>,
+    pkce: bool,
+    /// Scheme and authority of rocket, to resolve relative redirect urls.
+    origin: Option<String>,
+    redirect_url: RedirectUrl,
+    configured_redirect: bool,
+    cookie_prefix: String,
+    claims: Claims,
+    post_logout_redirect_urls: Vec<PostLogoutRedirectUrl>,
+    return_to: ReturnTo,
+    sessions: Option<Sessions>,
+    frontchannel_logout: bool,
+    check_session_interval: Duration,
+    /// Path at which the routes of the hatch are mounted, without a trailing `/`.
+    base: String,
+    refresh_tokens: bool,
+    token_refresh_margin: Duration,
+    refreshes: Refreshes,
+    states: LoginStates,
+}
+
+impl OidcHatch {
+    fn client(&self) -> Result<&OidcClient, OidcError> {
+        self.client.as_ref().ok_or(OidcError::NotConnected)
+    }
+
+    fn cookie(&self, name: &str) -> String {
+        format!("{}_{}", self.cookie_prefix, name)
+    }
+
+    /// The url the provider redirects back to after the login.
+    pub fn redirect_url(&self) -> &RedirectUrl {
+        &self.redirect_url
+    }
+
+    /// Whether logins use PKCE.
+    pub fn pkce(&self) -> bool {
+        self.pkce
+    }
+
+    /// Starts a login. Generates the url of the authorization endpoint of the provider, with a random
+    /// state, nonce and, unless disabled, a PKCE challenge.
+    pub fn authorize_url(&self) -> Result<Authorization, OidcError> {
+        let client = self.client()?.inner();
+        let mut request = client
+            .authorize_url(AuthenticationFlow::<CoreResponseType>::AuthorizationCode, CsrfToken::new_random, Nonce::new_random)
+            .add_scopes(self.scopes.iter().cloned())
+            .set_redirect_uri(Cow::Borrowed(&self.redirect_url));
+        let pkce_verifier = match self.pkce {
+            true => {
+                let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+                request = request.set_pkce_challenge(challenge);
+                Some(verifier)
+            },
+            false => None,
+        };
+        let (url, state, nonce) = request.url();
+
+        Ok(Authorization { url, state, nonce, pkce_verifier })
+    }
+
+    /// Exchanges the authorization code for tokens and verifies the ID token with the `nonce` of the login.
+    /// The `pkce_verifier` of the login is sent along, if it used PKCE. Returns the user together with
+    /// the tokens, whose ID token has been verified.
+    pub async fn exchange_code(&self, code: String, nonce: &Nonce, pkce_verifier: Option<PkceCodeVerifier>) -> Result<(OidcUser, CoreTokenResponse), OidcError> {
+        let comm = self.client()?;
+        let client = comm.inner();
+        let mut request = client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_redirect_uri(Cow::Borrowed(&self.redirect_url));
+        if let Some(verifier) = pkce_verifier {
+            request = request.set_pkce_verifier(verifier);
+        }
+        let response = request
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| OidcError::TokenExchange(Box::new(e)))?;
+
+        let id_token = response.id_token().ok_or(OidcError::MissingIdToken)?;
+        let claims = match id_token.claims(&client.id_token_verifier(), nonce) {
+            Err(ClaimsVerificationError::SignatureVerification(SignatureVerificationError::NoMatchingKey)) if comm.rotate().await => {
+                id_token.claims(&comm.inner().id_token_verifier(), nonce)
+            },
+            result => result,
+        }.map_err(OidcError::InvalidIdToken)?;
+
+        Ok((self.user(claims), response))
+    }
+
+    /// Refreshes the access token with `refresh_token`. If the provider issues a new ID token along with it,
+    /// the ID token is verified and the user is returned as stated by it.
+    pub async fn refresh_token(&self, refresh_token: &RefreshToken) -> Result<(Option<OidcUser>, CoreTokenResponse), OidcError> {
+        let comm = self.client()?;
+        let client = comm.inner();
+        let response = client
+            .exchange_refresh_token(refresh_token)
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| OidcError::TokenRefresh(Box::new(e)))?;
+
+        // An ID token of a refresh does not contain a nonce.
+        let user = match response.id_token() {
+            Some(id_token) => {
+                let claims = id_token.claims(&client.id_token_verifier(), |_: Option<&Nonce>| Ok(()))
+                    .map_err(OidcError::InvalidIdToken)?;
+                Some(self.user(claims))
+            },
+            None => None,
+        };
+        Ok((user, response))
+    }
+
+    /// Stores the user and the tokens of a login or refresh in private cookies. A refresh token or ID token
+    /// that the provider did not issue again is kept.
+    fn store(&self, cookies: &CookieJar<'_>, user: &OidcUser, tokens: &CoreTokenResponse) -> Result<(), serde_json::Error> {
+        cookies.add_private(flow::cookie(self.cookie("user"), serde_json::to_string(user)?));
+        cookies.add_private(flow::cookie(self.cookie("token"), tokens.access_token().secret().to_string()));
+        match tokens.expires_in() {
+            Some(expires_in) => {
+                let expires = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default() + expires_in;
+                cookies.add_private(flow::cookie(self.cookie("expires"), expires.as_secs().to_string()));
+            },
+            None => cookies.remove_private(self.cookie("expires")),
+        }
+        if let Some(refresh_token) = tokens.refresh_token().filter(|_| self.refresh_tokens) {
+            cookies.add_private(flow::cookie(self.cookie("refresh_token"), refresh_token.secret().to_string()));
+        }
+        if let Some(id_token) = tokens.id_token() {
+            cookies.add_private(flow::cookie(self.cookie("id_token"), id_token.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Refreshes the access token of the request, if it expires within `token_refresh_margin`. Returns
+    /// `false`, if the refresh failed and the user has to log in again.
+    async fn refresh_if_expiring(&self, request: &Request<'_>, user: &mut OidcUser) -> bool {
+        let cookies = request.cookies();
+        let expires = cookies.get_private(&self.cookie("expires"))
+            .and_then(|expires| expires.value().parse::<u64>().ok());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let expiring = expires.is_some_and(|expires| now + self.token_refresh_margin >= Duration::from_secs(expires));
+        let Some(refresh_token) = cookies.get_private(&self.cookie("refresh_token")).filter(|_| self.refresh_tokens && expiring) else {
+            return true;
+        };
+
+        let refreshed = self.refreshes.run(refresh_token.value(), async {
+            match self.refresh_token(&RefreshToken::new(refresh_token.value().to_string())).await {
+                Ok(refreshed) => Some(refreshed),
+                Err(e) => {
+                    warn_!("{}", e);
+                    None
+                },
+            }
+        }).await;
+        let stored = refreshed.and_then(|(refreshed_user, tokens)| {
+            match refreshed_user {
+                // The `sub` of a refreshed ID token has to be the same, see OpenID Connect Core 1.0, section 12.2.
+                Some(refreshed) if refreshed.subject != user.subject => {
+                    warn_!("Refreshed ID token is issued for `{}` instead of `{}`", refreshed.subject, user.subject);
+                    return None;
+                },
+                Some(refreshed) => *user = refreshed,
+                None => {},
+            }
+            user.access_token = Some(tokens.access_token().secret().to_string());
+            self.store(cookies, user, &tokens)
+                .map_err(|e| warn_!("Could not store OpenID Connect session: {}", e))
+                .ok()
+        });
+        let removed = request.local_cache(RemovedCookies::<Self>::none);
+        if stored.is_none() {
+            for name in ["user", "token", "expires", "refresh_token", "id_token", "session_state"] {
+                cookies.remove_private(self.cookie(name));
+                removed.names.lock().expect("Removed cookies are not poisoned").push(self.cookie(name));
+            }
+            return false;
+        }
+
+        let added = request.local_cache(AddedCookies::<Self>::none);
+        for name in ["user", "token", "expires", "refresh_token", "id_token"] {
+            match cookies.get_pending(&self.cookie(name)) {
+                Some(cookie) => added.cookies.lock().expect("Added cookies are not poisoned").push(cookie),
+                None => removed.names.lock().expect("Removed cookies are not poisoned").push(self.cookie(name)),
+            }
+        }
+        true
+    }
+
+    /// The url the provider redirects to after a logout, selected by `redirect`. It has to be one of the
+    /// `post_logout_redirect_urls`, after a relative one was resolved like the `redirect_url`. Without
+    /// `redirect`, it is the first of them, if there is any.
+    pub fn post_logout_redirect_url(&self, redirect: Option<&str>) -> Result<Option<&PostLogoutRedirectUrl>, OidcError> {
+        match redirect {
+            Some(redirect) => {
+                let redirect = flow::absolute(self.origin.as_deref(), redirect).unwrap_or_else(|| redirect.to_string());
+                self.post_logout_redirect_urls.iter()
+                    .find(|url| url.as_str() == redirect)
+                    .map(Some)
+                    .ok_or(OidcError::LogoutRedirect(redirect))
+            },
+            None => Ok(self.post_logout_redirect_urls.first()),
+        }
+    }
+
+    /// The url of the end session endpoint of the provider, which logs the user out at the provider and then
+    /// redirects to `post_logout_redirect_url`. `None` if the provider does not support RP-initiated logout.
+    pub fn end_session_url(&self, id_token: Option<&CoreIdToken>, post_logout_redirect_url: Option<&PostLogoutRedirectUrl>) -> Option<Url> {
+        let comm = self.client.as_ref()?;
+        let mut request = LogoutRequest::from(comm.end_session_url()?)
+            .set_client_id(comm.client_id.clone());
+        if let Some(id_token) = id_token {
+            request = request.set_id_token_hint(id_token);
+        }
+        if let Some(url) = post_logout_redirect_url {
+            request = request.set_post_logout_redirect_uri(url.clone());
+        }
+        Some(request.http_get_url())
+    }
+
+    /// The server-side sessions of the hatch, if `backchannel_logout` or `frontchannel_logout` is enabled.
+    pub fn sessions(&self) -> Option<&Sessions> {
+        self.sessions.as_ref()
+    }
+
+    /// The user stated by the claims of the ID token, with the claims picked as configured in `claims`.
+    fn user(&self, claims: &CoreIdTokenClaims) -> OidcUser {
+        let mapped = serde_json::to_value(claims)
+            .map(|claims| self.claims.map(&claims))
+            .unwrap_or_default();
+        let claim = |name: &str| mapped.get(name).and_then(|value| value.as_str()).map(str::to_string);
+        OidcUser {
+            subject: claim("subject").unwrap_or_else(|| claims.subject().to_string()),
+            preferred_username: claim("preferred_username"),
+            name: claim("name"),
+            email: claim("email"),
+            access_token: None,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Hatch for OidcHatch {
+    type Comm = OidcClient;
+    type Error = OidcError;
+    type Principal = OidcUser;
+
+    fn comm(&self) -> &OidcClient {
+        self.client.as_ref().expect("Communicator should have been connected")
+    }
+
+    fn connect_comm(&mut self, comm: Self::Comm) {
+        self.client = Some(comm);
+    }
+
+    fn name() -> &'static str {
+        "OpenID Connect"
+    }
+
+    fn routes() -> Vec<Route> {
+        routes![login, callback, denied, logout, backchannel_logout, frontchannel_logout, check_session]
+    }
+
+    fn login_uri(&self) -> Option<Origin<'static>> {
+        Some(Origin::parse("/login").expect("Valid login uri"))
+    }
+
+    fn docked(&mut self, name: &str, base: &Origin<'static>) {
+        self.cookie_prefix = format!("airlock_{}", name);
+        self.base = base.path().as_str().trim_end_matches('/').to_string();
+        if let (false, Some(origin)) = (self.configured_redirect, &self.origin) {
+            match RedirectUrl::new(flow::login_url(origin, base)) {
+                Ok(url) => self.redirect_url = url,
+                Err(e) => warn_!("Could not derive redirect url from mount base `{}`: {}", base, e),
+            }
+        }
+        info_!("Redirect url: {}", Paint::new(self.redirect_url.as_str()).underline());
+    }
+
+    async fn from(rocket: Rocket<Build>) -> HatchResult<Self, Self::Error> {
+        let figment = rocket.figment();
+        let config = match OidcConfig::from(figment) {
+            Ok(config) => config,
+            Err(e) => return Err((rocket, e.into())),
+        };
+        let origin = match flow::origin(figment) {
+            Ok(origin) => origin,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        let redirect_url = match config.redirect_url.as_deref() {
+            Some(url) => flow::absolute(origin.as_deref(), url),
+            None => origin.as_ref().map(|origin| format!("{}/login", origin)),
+        };
+        let redirect_url = match redirect_url.map(RedirectUrl::new) {
+            Some(Ok(url)) => url,
+            Some(Err(e)) => return Err((rocket, invalid("redirect_url", e).into())),
+            None => return Err((rocket, invalid("redirect_url", flow::UNKNOWN_ORIGIN).into())),
+        };
+
+        let mut post_logout_redirect_urls = Vec::new();
+        for url in &config.post_logout_redirect_urls {
+            match flow::absolute(origin.as_deref(), url).map(PostLogoutRedirectUrl::new) {
+                Some(Ok(url)) => post_logout_redirect_urls.push(url),
+                Some(Err(e)) => return Err((rocket, invalid("post_logout_redirect_urls", e).into())),
+                None => return Err((rocket, invalid("post_logout_redirect_urls", flow::UNKNOWN_ORIGIN).into())),
+            }
+        }
+        let return_to = match flow::return_to_origins(&registry::config_name::<OidcHatch>(), origin.as_deref(), &config.return_to_origins) {
+            Ok(return_to) => return_to,
+            Err(e) => return Err((rocket, e.into())),
+        };
+
+        let hatch = OidcHatch {
+            client: None,
+            scopes: config.scopes.into_iter().map(Scope::new).collect(),
+            pkce: config.pkce,
+            origin,
+            redirect_url,
+            configured_redirect: config.redirect_url.is_some(),
+            cookie_prefix: format!("airlock_{}", registry::config_name::<OidcHatch>()),
+            claims: config.claims,
+            post_logout_redirect_urls,
+            return_to,
+            sessions: (config.backchannel_logout || config.frontchannel_logout)
+                .then(|| Sessions::new(Duration::from_secs(config.session_lifetime))),
+            frontchannel_logout: config.frontchannel_logout,
+            check_session_interval: Duration::from_secs(config.check_session_interval),
+            base: String::new(),
+            refresh_tokens: config.refresh_tokens,
+            token_refresh_margin: Duration::from_secs(config.token_refresh_margin),
+            refreshes: Refreshes::default(),
+            states: LoginStates::of(&rocket),
+        };
+        Ok((rocket, hatch))
+    }
+
+    async fn authenticate(&self, request: &Request<'_>) -> Option<OidcUser> {
+        let cookies = request.cookies();
+        if let Some(sessions) = &self.sessions {
+            let session = cookies.get_private(&self.cookie("session"))?;
+            sessions.get(session.value())?;
+        }
+        let user = cookies.get_private(&self.cookie("user"))?;
+        let mut user = match serde_json::from_str::<OidcUser>(user.value()) {
+            Ok(user) => user,
+            Err(e) => {
+                warn_!("Discarding invalid OpenID Connect session: {}", e);
+                return None;
+            },
+        };
+        user.access_token = cookies.get_private(&self.cookie("token"))
+            .map(|token| token.value().to_string());
+        if !self.refresh_if_expiring(request, &mut user).await {
+            return None;
+        }
+        Some(user)
+    }
+}
+
+fn invalid(key: &str, e: impl fmt::Display) -> figment::Error {
+    flow::invalid(&registry::config_name::<OidcHatch>(), key, e)
+}
+
+#[get("/login?<return_to>", rank = 3)]
+async fn login(airlock: Airlock<OidcHatch, Mounted>, return_to: Option<String>, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+    let hatch = &airlock.hatch;
+    let return_to = flow::return_to(&hatch.return_to, return_to)?;
+    let authorization = hatch.authorize_url().map_err(|e| {
+        error_!("{}", e);
+        Status::InternalServerError
+    })?;
+    flow::begin(
+        &hatch.states,
+        cookies,
+        &hatch.cookie_prefix,
+        authorization.state.secret(),
+        Some(authorization.nonce.secret().to_string()),
+        authorization.pkce_verifier.as_ref().map(|verifier| verifier.secret().to_string()),
+        return_to,
+    ).await?;
+
+    info_!("Redirecting to {}", Paint::new(authorization.url.as_str()).underline());
+    Ok(Redirect::to(authorization.url.to_string()))
+}
+
+#[get("/login?<code>&<state>&<session_state>", rank = 1)]
+async fn callback(airlock: Airlock<OidcHatch, Mounted>, code: String, state: String, session_state: Option<String>, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+    let hatch = &airlock.hatch;
+    let login = flow::complete(&hatch.states, cookies, &hatch.cookie_prefix, &state).await?;
+    let Some(nonce) = login.nonce else {
+        warn_!("The nonce of the login flow is missing.");
+        return Err(Status::BadRequest);
+    };
+    let pkce_verifier = login.pkce_verifier.map(PkceCodeVerifier::new);
+    if hatch.pkce && pkce_verifier.is_none() {
+        warn_!("The PKCE code verifier of the login flow is missing.");
+        return Err(Status::BadRequest);
+    }
+
+    let (user, tokens) = hatch.exchange_code(code, &Nonce::new(nonce), pkce_verifier).await
+        .map_err(|e| {
+            error_!("{}", e);
+            match e {
+                OidcError::InvalidIdToken(_) | OidcError::MissingIdToken => Status::Unauthorized,
+                _ => Status::BadGateway,
+            }
+        })?;
+
+    hatch.store(cookies, &user, &tokens).map_err(|e| {
+        error_!("Could not store OpenID Connect session: {}", e);
+        Status::InternalServerError
+    })?;
+    if let Some(session_state) = session_state {
+        cookies.add_private(flow::cookie(hatch.cookie("session_state"), session_state));
+    }
+    if let Some(sessions) = &hatch.sessions {
+        // The ID token was verified by `exchange_code`, the `sid` is not part of its typed claims though.
+        let claims = tokens.id_token().and_then(|id_token| decode::<serde_json::Value>(id_token.to_string().split('.').nth(1)?));
+        let claim = |name: &str| claims.as_ref()?.get(name)?.as_str().map(str::to_string);
+        let subject = claim("sub").unwrap_or_else(|| user.subject.clone());
+        let id = sessions.start(&subject, claim("sid").as_deref());
+        cookies.add_private(flow::cookie(hatch.cookie("session"), id));
+    }
+
+    info_!("User `{}` logged in", user.preferred_username.as_deref().unwrap_or(&user.subject));
+    Ok(Redirect::to(login.return_to.unwrap_or_else(|| "/".to_string())))
+}
+
+#[get("/login?<error>&<error_description>", rank = 2)]
+fn denied(error: String, error_description: Option<String>) -> Status {
+    warn_!("OpenID Provider denied the login: {} {}", error, error_description.unwrap_or_default());
+    Status::Unauthorized
+}
+
+/// Ends the server-side session of the request, if there is one, and removes all cookies of airlock.
+fn end_session(hatch: &OidcHatch, cookies: &CookieJar<'_>) {
+    if let (Some(sessions), Some(session)) = (&hatch.sessions, cookies.get_private(&hatch.cookie("session"))) {
+        sessions.end(session.value());
+    }
+
+    let airlock_cookies: Vec<String> = cookies.iter()
+        .map(|cookie| cookie.name().to_string())
+        .filter(|name| name.starts_with("airlock_"))
+        .collect();
+    for name in airlock_cookies {
+        cookies.remove(Cookie::build(name).path("/"));
+    }
+}
+
+#[get("/logout?<redirect>")]
+fn logout(airlock: Airlock<OidcHatch, Mounted>, redirect: Option<String>, cookies: &CookieJar<'_>) -> Result<Redirect, Status> {
+    let hatch = &airlock.hatch;
+    let post_logout_redirect_url = hatch.post_logout_redirect_url(redirect.as_deref()).map_err(|e| {
+        warn_!("{}", e);
+        Status::BadRequest
+    })?;
+    let id_token = cookies.get_private(&hatch.cookie("id_token"))
+        .and_then(|id_token| CoreIdToken::from_str(id_token.value()).ok());
+    end_session(hatch, cookies);
+
+    match hatch.end_session_url(id_token.as_ref(), post_logout_redirect_url) {
+        Some(url) => {
+            info_!("Redirecting to {}", Paint::new(url.as_str()).underline());
+            Ok(Redirect::to(url.to_string()))
+        },
+        None => {
+            warn_!("The OpenID Provider does not support RP-initiated logout, only logging out locally.");
+            Ok(Redirect::to(post_logout_redirect_url.map_or_else(|| "/".to_string(), |url| url.to_string())))
+        },
+    }
+}
+
+#[derive(FromForm)]
+struct BackchannelLogout {
+    logout_token: String,
+}
+
+/// The answer to a back-channel logout, which must not be cached.
+#[derive(Responder)]
+struct NoStore(Custom<()>, Header<'static>);
+
+#[post("/backchannel-logout", data = "<form>")]
+async fn backchannel_logout(airlock: Airlock<OidcHatch, Mounted>, form: Form<BackchannelLogout>) -> NoStore {
+    let no_store = |status| NoStore(Custom(status, ()), Header::new("Cache-Control", "no-store"));
+    let hatch = &airlock.hatch;
+    let Some(sessions) = &hatch.sessions else {
+        warn_!("Received a back-channel logout, but `backchannel_logout` is not enabled.");
+        return no_store(Status::NotImplemented);
+    };
+    let Ok(client) = hatch.client() else {
+        return no_store(Status::InternalServerError);
+    };
+
+    match client.verify_logout_token(&form.logout_token).await {
+        Ok(logout) => {
+            let revoked = sessions.revoke(logout.subject.as_deref(), logout.sid.as_deref());
+            info_!("Back-channel logout of `{}` ended {} session(s)",
+                logout.sid.as_deref().or(logout.subject.as_deref()).unwrap_or_default(), revoked);
+            no_store(Status::Ok)
+        },
+        Err(e) => {
+            warn_!("{}", e);
+            no_store(Status::BadRequest)
+        },
+    }
+}
+
+/// A page that is shown in an iframe, which the provider may embed, but no one else. Browsers ignore the
+/// `X-Frame-Options` header of rocket's shield, if `frame-ancestors` is set.
+#[derive(Responder)]
+#[response(content_type = "html")]
+struct Framed(String, Header<'static>, Header<'static>);
+
+impl Framed {
+    fn new(hatch: &OidcHatch, html: String) -> Self {
+        let provider = Url::parse(hatch.comm().issuer_url.as_str())
+            .map(|url| url.origin().ascii_serialization())
+            .unwrap_or_default();
+        Framed(
+            html,
+            Header::new("Cache-Control", "no-store"),
+            Header::new("Content-Security-Policy", format!("frame-ancestors 'self' {}", provider)),
+        )
+    }
+}
+
+#[get("/frontchannel-logout?<iss>&<sid>")]
+fn frontchannel_logout(airlock: Airlock<OidcHatch, Mounted>, iss: Option<String>, sid: Option<String>, cookies: &CookieJar<'_>) -> Result<Framed, Status> {
+    let hatch = &airlock.hatch;
+    let Some(sessions) = hatch.sessions.as_ref().filter(|_| hatch.frontchannel_logout) else {
+        warn_!("Received a front-channel logout, but `frontchannel_logout` is not enabled.");
+        return Err(Status::NotImplemented);
+    };
+    if iss.as_ref().is_some_and(|iss| iss != hatch.comm().issuer_url.as_str()) {
+        warn_!("The `iss` of the front-channel logout is not the issuer of the OpenID Provider.");
+        return Err(Status::BadRequest);
+    }
+
+    // The cookies are only sent along, if the browser allows them in a third-party iframe, so
+    // the session is ended by its `sid` as well.
+    if let (Some(_), Some(sid)) = (&iss, &sid) {
+        let revoked = sessions.revoke(None, Some(sid));
+        info_!("Front-channel logout of `{}` ended {} session(s)", sid, revoked);
+    }
+    end_session(hatch, cookies);
+
+    Ok(Framed::new(hatch, "<!DOCTYPE html><html><head><title>Logged out</title></head><body></body></html>".into()))
+}
+
+#[derive(Serialize)]
+struct CheckSession {
+    client_id: String,
+    session_state: String,
+    provider: String,
+    interval: u128,
+    changed: String,
+}
+
+#[get("/check-session")]
+fn check_session(airlock: Airlock<OidcHatch, Mounted>, cookies: &CookieJar<'_>) -> Result<Framed, Status> {
+    let hatch = &airlock.hatch;
+    let comm = hatch.client().map_err(|_| Status::InternalServerError)?;
+    let Some(iframe) = comm.check_session_iframe() else {
+        warn_!("The OpenID Provider does not support Session Management.");
+        return Err(Status::NotFound);
+    };
+    let session_state = cookies.get_private(&hatch.cookie("session_state")).ok_or(Status::NoContent)?;
+
+    let config = CheckSession {
+        client_id: comm.client_id.to_string(),
+        session_state: session_state.value().to_string(),
+        provider: iframe.origin().ascii_serialization(),
+        interval: hatch.check_session_interval.as_millis(),
+        changed: format!("{}/logout", hatch.base),
+    };
+    // Escaped, so no value can end the script element.
+    let config = serde_json::to_string(&config).map_err(|_| Status::InternalServerError)?.replace('<', "\\u003c");
+    let iframe = iframe.as_str().replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+    Ok(Framed::new(hatch, format!(r#"<!DOCTYPE html>
+<html>
+<head><title>Check session</title></head>
+<body>
+<iframe id="op" src="{iframe}" hidden></iframe>
+<script>
+const config = {config};
+const op = document.getElementById("op");
+let timer;
+const check = () => op.contentWindow.postMessage(config.client_id + " " + config.session_state, config.provider);
+window.addEventListener("message", (event) => {{
+    if (event.origin !== config.provider || event.source !== op.contentWindow) return;
+    if (event.data === "changed") {{
+        clearInterval(timer);
+        window.top.location.href = config.changed;
+    }} else if (event.data === "error") {{
+        clearInterval(timer);
+    }}
+}});
+op.addEventListener("load", () => {{
+    check();
+    timer = setInterval(check, config.interval);
+}});
+</script>
+</body>
+</html>"#)))
+}
@@ -0,0 +1,293 @@
+//! Presets for common identity providers. Instead of looking up the endpoints of a provider, the config
+//! table of an OpenID Connect or OAuth2 hatch can name the provider and the few parameters its endpoints
+//! are made of, e.g.:
+//!
+//! ```toml
+//! [default.airlock.openidconnect]
+//! provider = "keycloak"
+//! base_url = "http://localhost:8080"
+//! realm = "demo"
+//! client_id = "my-app"
+//! client_secret = "s3cr3t"
+//! ```
+//!
+//! The preset fills in the endpoints, the scopes and the `claims` table, which maps the claims of the
+//! provider to the standard `subject`, `preferred_username`, `name` and `email`. The claims differ between
+//! the protocols, e.g. the ID tokens of GitLab and Microsoft have the standard claims, their userinfo
+//! APIs have not. Every field that is
+//! set in the config table overrides the one of the preset, and so does every single entry of `claims`.
+//!
+//! | `provider`  | Parameters                                   | Hatches                |
+//! |-------------|----------------------------------------------|------------------------|
+//! | `keycloak`  | `base_url`, `realm`                          | OpenID Connect, OAuth2 |
+//! | `github`    |                                              | OAuth2                 |
+//! | `gitlab`    | `base_url`, defaults to `https://gitlab.com` | OpenID Connect, OAuth2 |
+//! | `google`    |                                              | OpenID Connect, OAuth2 |
+//! | `microsoft` | `tenant`, the tenant id for OpenID Connect   | OpenID Connect, OAuth2 |
+//! | `auth0`     | `domain`, e.g. of Auth0 or Okta              | OpenID Connect, OAuth2 |
+//!
+//! Only available with the `oidc` or `oauth2` feature.
+
+use rocket::figment::{
+    self, Figment,
+    providers::Serialized,
+    value::{Dict, Value},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+
+
+/// A preset for an identity provider, selected with `provider` in the config table of a hatch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Keycloak,
+    GitHub,
+    GitLab,
+    Google,
+    /// Microsoft Entra ID, formerly known as Azure Active Directory.
+    #[serde(alias = "entra", alias = "azure")]
+    Microsoft,
+    /// Auth0 and providers with the same endpoints, e.g. Okta.
+    Auth0,
+}
+
+/// The protocol a hatch speaks, which decides which endpoints and scopes of a preset it uses.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(all(feature = "oidc", feature = "oauth2")), allow(dead_code))]
+pub(crate) enum Flavor {
+    Oidc,
+    OAuth2,
+}
+
+/// Endpoint templates, scopes and claim names of a [`Provider`]. Templates contain parameters like `{realm}`.
+struct Preset {
+    /// Parameters with a default value.
+    defaults: &'static [(&'static str, &'static str)],
+    discover_url: Option<&'static str>,
+    auth_url: &'static str,
+    token_url: &'static str,
+    userinfo_url: &'static str,
+    oidc_scopes: &'static [&'static str],
+    oauth2_scopes: &'static [&'static str],
+    /// Claims of the ID token, which differ from the standard ones.
+    oidc_claims: &'static [(&'static str, &'static str)],
+    /// Claims of the userinfo, which differ from the standard ones.
+    oauth2_claims: &'static [(&'static str, &'static str)],
+}
+
+/// Parameters that may appear in the templates of a preset.
+const PARAMETERS: [&str; 4] = ["base_url", "realm", "tenant", "domain"];
+
+impl Provider {
+    fn preset(self) -> Preset {
+        match self {
+            Provider::Keycloak => Preset {
+                defaults: &[],
+                discover_url: Some("{base_url}/realms/{realm}"),
+                auth_url: "{base_url}/realms/{realm}/protocol/openid-connect/auth",
+                token_url: "{base_url}/realms/{realm}/protocol/openid-connect/token",
+                userinfo_url: "{base_url}/realms/{realm}/protocol/openid-connect/userinfo",
+                oidc_scopes: &["profile", "email"],
+                oauth2_scopes: &["openid", "profile", "email"],
+                oidc_claims: &[],
+                oauth2_claims: &[],
+            },
+            Provider::GitHub => Preset {
+                defaults: &[],
+                discover_url: None,
+                auth_url: "https://github.com/login/oauth/authorize",
+                token_url: "https://github.com/login/oauth/access_token",
+                userinfo_url: "https://api.github.com/user",
+                oidc_scopes: &[],
+                oauth2_scopes: &["read:user", "user:email"],
+                oidc_claims: &[],
+                oauth2_claims: &[("subject", "id"), ("preferred_username", "login")],
+            },
+            Provider::GitLab => Preset {
+                defaults: &[("base_url", "https://gitlab.com")],
+                discover_url: Some("{base_url}"),
+                auth_url: "{base_url}/oauth/authorize",
+                token_url: "{base_url}/oauth/token",
+                userinfo_url: "{base_url}/api/v4/user",
+                oidc_scopes: &["profile", "email"],
+                oauth2_scopes: &["read_user"],
+                oidc_claims: &[],
+                oauth2_claims: &[("subject", "id"), ("preferred_username", "username")],
+            },
+            Provider::Google => Preset {
+                defaults: &[],
+                discover_url: Some("https://accounts.google.com"),
+                auth_url: "https://accounts.google.com/o/oauth2/v2/auth",
+                token_url: "https://oauth2.googleapis.com/token",
+                userinfo_url: "https://www.googleapis.com/oauth2/v3/userinfo",
+                oidc_scopes: &["profile", "email"],
+                oauth2_scopes: &["profile", "email"],
+                oidc_claims: &[("preferred_username", "email")],
+                oauth2_claims: &[("preferred_username", "email")],
+            },
+            Provider::Microsoft => Preset {
+                defaults: &[],
+                discover_url: Some("https://login.microsoftonline.com/{tenant}/v2.0"),
+                auth_url: "https://login.microsoftonline.com/{tenant}/oauth2/v2.0/authorize",
+                token_url: "https://login.microsoftonline.com/{tenant}/oauth2/v2.0/token",
+                userinfo_url: "https://graph.microsoft.com/v1.0/me",
+                oidc_scopes: &["profile", "email"],
+                oauth2_scopes: &["User.Read"],
+                oidc_claims: &[],
+                oauth2_claims: &[
+                    ("subject", "id"),
+                    ("preferred_username", "userPrincipalName"),
+                    ("name", "displayName"),
+                    ("email", "mail"),
+                ],
+            },
+            Provider::Auth0 => Preset {
+                defaults: &[],
+                discover_url: Some("https://{domain}/"),
+                auth_url: "https://{domain}/authorize",
+                token_url: "https://{domain}/oauth/token",
+                userinfo_url: "https://{domain}/userinfo",
+                oidc_scopes: &["profile", "email"],
+                oauth2_scopes: &["openid", "profile", "email"],
+                oidc_claims: &[("preferred_username", "nickname")],
+                oauth2_claims: &[("preferred_username", "nickname")],
+            },
+        }
+    }
+}
+
+impl Preset {
+    /// The config table this preset provides for a hatch of `flavor`, filled with the parameters of `table`.
+    #[allow(clippy::result_large_err)]
+    fn table(&self, provider: Provider, flavor: Flavor, table: &Dict, path: &str) -> Result<Dict, figment::Error> {
+        let fill = |template: &str| -> Result<String, figment::Error> {
+            let mut url = template.to_string();
+            for parameter in PARAMETERS.iter().filter(|p| template.contains(&format!("{{{}}}", p))) {
+                let value = table.get(*parameter)
+                    .and_then(Value::as_str)
+                    .or_else(|| self.defaults.iter().find(|(p, _)| p == parameter).map(|(_, v)| *v))
+                    .ok_or_else(|| figment::Error::from(figment::error::Kind::MissingField((*parameter).into()))
+                        .with_path(&format!("{}.{}", path, parameter)))?;
+                url = url.replace(&format!("{{{}}}", parameter), value.trim_end_matches('/'));
+            }
+            Ok(url)
+        };
+
+        let mut preset = Dict::new();
+        let (scopes, claims) = match flavor {
+            Flavor::Oidc => {
+                let discover_url = self.discover_url.ok_or_else(|| {
+                    figment::Error::from(format!("provider `{:?}` does not support OpenID Connect", provider))
+                        .with_path(&format!("{}.provider", path))
+                })?;
+                preset.insert("discover_url".into(), fill(discover_url)?.into());
+                (self.oidc_scopes, self.oidc_claims)
+            },
+            Flavor::OAuth2 => {
+                preset.insert("auth_url".into(), fill(self.auth_url)?.into());
+                preset.insert("token_url".into(), fill(self.token_url)?.into());
+                preset.insert("userinfo_url".into(), fill(self.userinfo_url)?.into());
+                (self.oauth2_scopes, self.oauth2_claims)
+            },
+        };
+        preset.insert("scopes".into(), scopes.to_vec().into());
+        let claims: Dict = claims.iter().map(|(claim, source)| (claim.to_string(), (*source).into())).collect();
+        preset.insert("claims".into(), claims.into());
+
+        Ok(preset)
+    }
+}
+
+/// Extracts the config table `airlock.<table>` of a hatch. If it names a `provider`, the preset of the
+/// provider fills in every field the table does not set.
+#[allow(clippy::result_large_err)]
+pub(crate) fn extract<T: DeserializeOwned>(figment: &Figment, table: &str, flavor: Flavor) -> Result<T, figment::Error> {
+    let path = format!("airlock.{}", table);
+    let dict = figment.find_value(&path).ok().and_then(|value| value.into_dict());
+    let provider = match dict.as_ref().and_then(|dict| dict.get("provider")) {
+        Some(_) => Some(figment.extract_inner::<Provider>(&format!("{}.provider", path))?),
+        None => None,
+    };
+
+    match (provider, dict) {
+        (Some(provider), Some(dict)) => {
+            let preset = provider.preset().table(provider, flavor, &dict, &path)?;
+            figment.clone()
+                .join(Serialized::default(&path, preset))
+                .extract_inner(&path)
+        },
+        _ => figment.extract_inner(&path),
+    }
+}
+
+/// Which claim of the provider holds each of the standard claims, as configured in the `claims` table.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct Claims {
+    subject: String,
+    preferred_username: String,
+    name: String,
+    email: String,
+}
+
+impl Default for Claims {
+    fn default() -> Self {
+        Claims {
+            subject: "sub".into(),
+            preferred_username: "preferred_username".into(),
+            name: "name".into(),
+            email: "email".into(),
+        }
+    }
+}
+
+impl Claims {
+    /// Picks the standard claims out of the claims of the provider. Numbers and booleans are turned into
+    /// strings, e.g. the numeric user ids of GitHub, and claims that are missing or not scalar are skipped.
+    pub(crate) fn map(&self, claims: &serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        [
+            ("subject", &self.subject),
+            ("preferred_username", &self.preferred_username),
+            ("name", &self.name),
+            ("email", &self.email),
+        ].into_iter()
+            .filter_map(|(claim, source)| {
+                let value = match claims.get(source)? {
+                    serde_json::Value::String(s) => s.clone(),
+                    serde_json::Value::Number(n) => n.to_string(),
+                    serde_json::Value::Bool(b) => b.to_string(),
+                    _ => return None,
+                };
+                Some((claim.to_string(), serde_json::Value::String(value)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(provider: Provider, flavor: Flavor) -> Dict {
+        let table: Dict = [("tenant".to_string(), Value::from("common"))].into_iter().collect();
+        let preset = provider.preset().table(provider, flavor, &table, "airlock.test").unwrap();
+        preset["claims"].clone().into_dict().unwrap()
+    }
+
+    #[test]
+    fn id_tokens_have_the_standard_claims() {
+        assert!(claims(Provider::GitLab, Flavor::Oidc).is_empty());
+        assert!(claims(Provider::Microsoft, Flavor::Oidc).is_empty());
+    }
+
+    #[test]
+    fn userinfo_claims_are_mapped() {
+        let gitlab = claims(Provider::GitLab, Flavor::OAuth2);
+        assert_eq!(gitlab["subject"].as_str(), Some("id"));
+        assert_eq!(gitlab["preferred_username"].as_str(), Some("username"));
+
+        let microsoft = claims(Provider::Microsoft, Flavor::OAuth2);
+        assert_eq!(microsoft["subject"].as_str(), Some("id"));
+        assert_eq!(microsoft["email"].as_str(), Some("mail"));
+    }
+}
@@ -0,0 +1,270 @@
+//! Role-based access control. Roles, their permissions and which roles they inherit from are
+//! read from the `airlock.rbac` table of the rocket config, e.g.:
+//!
+//! ```toml
+//! [default.airlock.rbac.roles.viewer]
+//! permissions = ["articles:read"]
+//!
+//! [default.airlock.rbac.roles.editor]
+//! permissions = ["articles:write"]
+//! inherits = ["viewer"]
+//!
+//! [default.airlock.rbac.roles.admin]
+//! permissions = ["users:manage"]
+//! inherits = ["editor"]
+//! ```
+//!
+//! Attach [`Rbac::fairing()`] and routes can require a permission instead of a role with the
+//! [`HasPermission`] policy. Principals need to implement [`Roles`], their effective permissions
+//! are those of all their roles, including inherited ones. Cycles in the role inheritance are
+//! rejected at ignite time.
+
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use rocket::{
+    error, error_, Ignite, info, info_, Rocket,
+    fairing::{AdHoc, Fairing},
+    figment::{self, Figment},
+    request::Request,
+};
+use serde::Deserialize;
+use yansi::Paint;
+use crate::bulkhead::{Breach, Bulkhead, Label, Roles};
+
+
+#[derive(Debug, Default, Deserialize)]
+struct RbacConfig {
+    #[serde(default)]
+    roles: HashMap<String, RoleConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RoleConfig {
+    #[serde(default)]
+    permissions: Vec<String>,
+    #[serde(default)]
+    inherits: Vec<String>,
+}
+
+/// The effective permissions of all configured roles.
+#[derive(Debug)]
+pub struct Rbac {
+    permissions: HashMap<String, HashSet<String>>,
+}
+
+impl Rbac {
+    /// Reads roles, permissions and role inheritance from `airlock.rbac`, resolves the effective permissions
+    /// of every role and installs the result into rocket, where it is used by the [`HasPermission`] policy.
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("RBAC", |rocket| async {
+            let emoji = if cfg!(windows) {""} else {"🛡️ "};
+            info!("{}{}", Paint::mask(emoji), Paint::magenta("Airlock RBAC:").wrap());
+
+            match Rbac::from(rocket.figment()) {
+                Ok(rbac) => {
+                    info_!("Loaded {} roles", rbac.permissions.len());
+                    Ok(rocket.manage(rbac))
+                },
+                Err(e) => {
+                    error_!("Error parsing config for RBAC: {}", e);
+                    Err(rocket)
+                },
+            }
+        })
+    }
+
+    /// Reads and resolves the roles configured in `airlock.rbac`. A missing table results in no roles.
+    #[allow(clippy::result_large_err)]
+    pub fn from(figment: &Figment) -> Result<Self, figment::Error> {
+        let config = match figment.find_value("airlock.rbac") {
+            Ok(_) => figment.extract_inner::<RbacConfig>("airlock.rbac")?,
+            Err(_) => RbacConfig::default(),
+        };
+
+        let mut permissions = HashMap::new();
+        for role in config.roles.keys() {
+            let mut resolved = HashSet::new();
+            resolve(&config, role, &mut Vec::new(), &mut resolved)?;
+            permissions.insert(role.clone(), resolved);
+        }
+
+        Ok(Rbac { permissions })
+    }
+
+    /// The effective permissions of a single role, or `None` if the role is unknown.
+    pub fn role(&self, role: &str) -> Option<&HashSet<String>> {
+        self.permissions.get(role)
+    }
+
+    /// The effective permissions of a principal, which are the permissions of all its roles.
+    /// Roles that are not configured grant no permissions.
+    pub fn permissions<'a, P: Roles>(&'a self, principal: &'a P) -> HashSet<&'a str> {
+        principal.roles().iter()
+            .filter_map(|role| self.role(role))
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Whether any of the roles of the principal grants the permission.
+    pub fn has_permission<P: Roles>(&self, principal: &P, permission: &str) -> bool {
+        principal.roles().iter()
+            .filter_map(|role| self.role(role))
+            .any(|permissions| permissions.contains(permission))
+    }
+}
+
+/// Collects the permissions of `role` and all roles it inherits from into `resolved`. `path` holds the
+/// chain of roles that lead to `role`, so that inheritance cycles can be detected.
+#[allow(clippy::result_large_err)]
+fn resolve(config: &RbacConfig, role: &str, path: &mut Vec<String>, resolved: &mut HashSet<String>) -> Result<(), figment::Error> {
+    if path.iter().any(|r| r == role) {
+        path.push(role.to_string());
+        return Err(figment::Error::from(format!("role inheritance cycle: {}", path.join(" -> ")))
+            .with_path("airlock.rbac.roles"));
+    }
+
+    let Some(config_role) = config.roles.get(role) else {
+        return Err(figment::Error::from(format!("role `{}` inherits from unknown role `{}`", path.last().map(String::as_str).unwrap_or_default(), role))
+            .with_path("airlock.rbac.roles"));
+    };
+
+    path.push(role.to_string());
+    resolved.extend(config_role.permissions.iter().cloned());
+    for parent in &config_role.inherits {
+        resolve(config, parent, path, resolved)?;
+    }
+    path.pop();
+
+    Ok(())
+}
+
+/// Passes if one of the roles of the principal grants the permission `L`, see [`Rbac`].
+pub struct HasPermission<L: Label>(PhantomData<fn() -> L>);
+
+#[rocket::async_trait]
+impl<P: Roles + Send + Sync, L: Label> Bulkhead<P> for HasPermission<L> {
+    async fn inspect(principal: &P, request: &Request<'_>) -> Result<(), Breach> {
+        let Some(rbac) = request.rocket().state::<Rbac>() else {
+            error_!("RBAC was not installed into the airlock. Attach `Rbac::fairing()`.");
+            return Err(Breach::failure("RBAC is not installed"));
+        };
+
+        match rbac.has_permission(principal, L::NAME) {
+            true => Ok(()),
+            false => Err(Breach::new(format!("missing {}", <Self as Bulkhead<P>>::describe()))),
+        }
+    }
+
+    fn describe() -> String {
+        format!("permission `{}`", L::NAME)
+    }
+
+    fn abort(rocket: &Rocket<Ignite>) -> bool {
+        if rocket.state::<Rbac>().is_some() {
+            return false;
+        }
+
+        error!("Permission `{}` is required by a route, but RBAC was not installed into the airlock.", L::NAME);
+        info_!("Attach its fairing with `.attach(Rbac::fairing())`.");
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::figment::{Figment, providers::{Format, Toml}};
+    use super::Rbac;
+
+    #[allow(clippy::result_large_err)]
+    fn rbac(toml: &str) -> Result<Rbac, rocket::figment::Error> {
+        Rbac::from(&Figment::from(Toml::string(toml).nested()))
+    }
+
+    #[test]
+    fn resolves_inherited_permissions() {
+        let rbac = rbac(r#"
+            [default.airlock.rbac.roles.viewer]
+            permissions = ["articles:read"]
+
+            [default.airlock.rbac.roles.editor]
+            permissions = ["articles:write"]
+            inherits = ["viewer"]
+        "#).unwrap();
+
+        let editor = rbac.role("editor").unwrap();
+        assert_eq!(editor.len(), 2);
+        assert!(editor.contains("articles:read"));
+        assert!(editor.contains("articles:write"));
+        assert_eq!(rbac.role("viewer").unwrap().len(), 1);
+        assert!(rbac.role("admin").is_none());
+    }
+
+    #[test]
+    fn resolves_diamond_inheritance() {
+        let rbac = rbac(r#"
+            [default.airlock.rbac.roles.viewer]
+            permissions = ["articles:read"]
+
+            [default.airlock.rbac.roles.editor]
+            permissions = ["articles:write"]
+            inherits = ["viewer"]
+
+            [default.airlock.rbac.roles.reviewer]
+            permissions = ["articles:approve"]
+            inherits = ["viewer"]
+
+            [default.airlock.rbac.roles.admin]
+            inherits = ["editor", "reviewer"]
+        "#).unwrap();
+
+        let admin = rbac.role("admin").unwrap();
+        assert_eq!(admin.len(), 3);
+        assert!(["articles:read", "articles:write", "articles:approve"].iter().all(|p| admin.contains(*p)));
+    }
+
+    #[test]
+    fn rejects_self_cycle() {
+        let e = rbac(r#"
+            [default.airlock.rbac.roles.admin]
+            permissions = ["users:manage"]
+            inherits = ["admin"]
+        "#).unwrap_err();
+
+        assert!(e.to_string().contains("role inheritance cycle: admin -> admin"), "{}", e);
+    }
+
+    #[test]
+    fn rejects_longer_cycle() {
+        let e = rbac(r#"
+            [default.airlock.rbac.roles.viewer]
+            inherits = ["admin"]
+
+            [default.airlock.rbac.roles.editor]
+            inherits = ["viewer"]
+
+            [default.airlock.rbac.roles.admin]
+            inherits = ["editor"]
+        "#).unwrap_err();
+
+        let cycle = e.to_string();
+        assert!(cycle.contains("role inheritance cycle"), "{}", e);
+        assert!(["viewer", "editor", "admin"].iter().all(|role| cycle.contains(role)), "{}", e);
+    }
+
+    #[test]
+    fn rejects_unknown_parent() {
+        let e = rbac(r#"
+            [default.airlock.rbac.roles.editor]
+            inherits = ["viewer"]
+        "#).unwrap_err();
+
+        assert!(e.to_string().contains("role `editor` inherits from unknown role `viewer`"), "{}", e);
+    }
+
+    #[test]
+    fn missing_table_has_no_roles() {
+        let rbac = rbac("[default.airlock]").unwrap();
+        assert!(rbac.role("viewer").is_none());
+    }
+}
@@ -0,0 +1,154 @@
+use std::{any::{Any, TypeId}, collections::HashMap, marker::PhantomData, sync::{Arc, RwLock}};
+use rocket::{Build, error_, Request, Rocket, Route, http::{Method, uri::Origin}};
+use crate::{Airlock, Hatch, Instance};
+
+
+/// A hatch installed into the airlock, with its concrete type erased. Lets parts of the airlock that only
+/// know a hatch by its name, e.g. from the rocket config, authenticate requests with it.
+#[rocket::async_trait]
+pub(crate) trait Installed: Send + Sync {
+    /// The `TypeId` of the hatch.
+    fn hatch_type(&self) -> TypeId;
+
+    /// Authenticates the request with the hatch, using the cache of [`Airlock::authenticate`].
+    async fn principal(&self, request: &Request<'_>) -> Option<Arc<dyn Any + Send + Sync>>;
+
+    async fn authenticate(&self, request: &Request<'_>) -> bool {
+        self.principal(request).await.is_some()
+    }
+}
+
+struct Handle<H, I>(PhantomData<fn() -> (H, I)>);
+
+#[rocket::async_trait]
+impl<H: Hatch + 'static, I: Instance> Installed for Handle<H, I> {
+    fn hatch_type(&self) -> TypeId {
+        TypeId::of::<H>()
+    }
+
+    async fn principal(&self, request: &Request<'_>) -> Option<Arc<dyn Any + Send + Sync>> {
+        Airlock::<H, I>::authenticate(request).await
+            .map(|principal| principal as Arc<dyn Any + Send + Sync>)
+    }
+}
+
+/// An instance of a hatch, where it was mounted and the routes it mounted there.
+struct Dock {
+    hatch: Arc<dyn Any + Send + Sync>,
+    base: Origin<'static>,
+    installed: Arc<dyn Installed>,
+    routes: Vec<(Method, String)>,
+}
+
+impl Dock {
+    fn airlock<H: Hatch + 'static, I: Instance>(&self) -> Option<Airlock<H, I>> {
+        let hatch = self.hatch.clone().downcast::<H>().ok()?;
+        Some(Airlock::docked_at(hatch, self.base.clone()))
+    }
+}
+
+/// All hatches installed into the airlock, by the name of their instance.
+#[derive(Default)]
+pub(crate) struct Hatches(RwLock<HashMap<String, Dock>>);
+
+impl Hatches {
+    /// Registers the instance `I` of the hatch `H` under `name`, together with the routes it mounted at `base`.
+    /// Manages the registry first, if this is the first hatch. Fails if the name is already taken.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn register<H: Hatch + 'static, I: Instance>(
+        rocket: Rocket<Build>,
+        name: String,
+        hatch: Arc<H>,
+        base: Origin<'static>,
+        routes: &[Route],
+    ) -> Result<Rocket<Build>, Rocket<Build>> {
+        let rocket = match rocket.state::<Hatches>() {
+            Some(_) => rocket,
+            None => rocket.manage(Hatches::default()),
+        };
+
+        let mut registry = rocket.state::<Hatches>()
+            .expect("Registry of hatches was just managed")
+            .0.write()
+            .expect("Registry of hatches is not poisoned");
+        if registry.contains_key(&name) {
+            error_!("An airlock hatch named `{}` is already installed.", name);
+            drop(registry);
+            return Err(rocket);
+        }
+
+        let routes = routes.iter()
+            .filter_map(|route| route.clone().map_base(|old| format!("{}{}", base, old)).ok())
+            .map(|route| (route.method, route.uri.path().to_string()))
+            .collect();
+        registry.insert(name, Dock { hatch, base, installed: Arc::new(Handle::<H, I>(PhantomData)), routes });
+        drop(registry);
+
+        Ok(rocket)
+    }
+
+    /// The airlock of the hatch `H` that was installed under `name`.
+    pub(crate) fn airlock<H: Hatch + 'static, I: Instance>(&self, name: &str) -> Option<Airlock<H, I>> {
+        self.0.read()
+            .expect("Registry of hatches is not poisoned")
+            .get(name)
+            .and_then(Dock::airlock)
+    }
+
+    /// The airlock of the instance of hatch `H` that mounted `route`, if any.
+    pub(crate) fn airlock_of<H: Hatch + 'static, I: Instance>(&self, route: &Route) -> Option<Airlock<H, I>> {
+        let path = route.uri.path();
+        self.0.read()
+            .expect("Registry of hatches is not poisoned")
+            .values()
+            .filter(|dock| dock.routes.iter().any(|(method, p)| *method == route.method && p == path))
+            .find_map(Dock::airlock)
+    }
+
+    /// Whether any instance of the hatch `H` is installed.
+    pub(crate) fn contains<H: Hatch + 'static>(&self) -> bool {
+        self.0.read()
+            .expect("Registry of hatches is not poisoned")
+            .values()
+            .any(|dock| dock.hatch.is::<H>())
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<dyn Installed>> {
+        self.0.read()
+            .expect("Registry of hatches is not poisoned")
+            .get(name)
+            .map(|dock| dock.installed.clone())
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.0.read()
+            .expect("Registry of hatches is not poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    pub(crate) fn all(&self) -> Vec<Arc<dyn Installed>> {
+        self.0.read()
+            .expect("Registry of hatches is not poisoned")
+            .values()
+            .map(|dock| dock.installed.clone())
+            .collect()
+    }
+
+    /// Method and path of all routes mounted by hatches.
+    pub(crate) fn routes(&self) -> Vec<(Method, String)> {
+        self.0.read()
+            .expect("Registry of hatches is not poisoned")
+            .values()
+            .flat_map(|dock| dock.routes.iter().cloned())
+            .collect()
+    }
+}
+
+/// The name of the hatch `H`, as it is used for its config table `airlock.<name>`.
+pub(crate) fn config_name<H: Hatch>() -> String {
+    H::name().replace(' ', "").to_lowercase()
+}
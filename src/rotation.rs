@@ -0,0 +1,35 @@
+//! Refreshing the keys of a provider, when a token is signed with a key that is not known yet.
+
+use std::{future::Future, time::{Duration, Instant}};
+use rocket::tokio::sync::Mutex;
+
+
+/// How long after a refresh an unknown key does not trigger another one, so tokens with made up key ids
+/// can not be used to flood the provider with requests.
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Refreshes keys on demand. Only one refresh runs at a time, callers that ask for one while it runs
+/// wait for it and share its result instead of starting their own.
+#[derive(Default)]
+pub(crate) struct Rotation(Mutex<Option<(Instant, bool)>>);
+
+impl Rotation {
+    /// Runs `refresh`, unless the keys were refreshed less than [`COOLDOWN`] ago. Returns whether the keys
+    /// were refreshed successfully since this was called.
+    pub(crate) async fn rotate(&self, refresh: impl Future<Output = bool>) -> bool {
+        let requested = Instant::now();
+        let mut last = self.0.lock().await;
+        if let Some((at, refreshed)) = *last {
+            if at >= requested {
+                return refreshed;
+            }
+            if at.elapsed() < COOLDOWN {
+                return false;
+            }
+        }
+
+        let refreshed = refresh.await;
+        *last = Some((Instant::now(), refreshed));
+        refreshed
+    }
+}
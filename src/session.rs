@@ -0,0 +1,92 @@
+//! Server-side sessions. The private cookies of a hatch can not be taken back once they were handed out,
+//! so a hatch that has to end sessions on behalf of the provider, e.g. with OpenID Connect Back-Channel
+//! or Front-Channel Logout, keeps every session in a [`Sessions`] registry as well. Its cookie then only
+//! holds the id of the session, which is valid as long as the registry knows it.
+//!
+//! Sessions are kept in memory, so they end when rocket is restarted.
+//!
+//! Only available with the `oidc` feature.
+
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+use openidconnect::CsrfToken;
+
+
+/// A session of a user that logged in at a provider.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// The `sub` claim of the user at the provider.
+    pub subject: String,
+    /// The `sid` claim, which identifies the session at the provider, if the provider issued one.
+    pub sid: Option<String>,
+    expires: Instant,
+}
+
+/// The registry of all sessions of a hatch, by session id.
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+    lifetime: Duration,
+}
+
+impl Sessions {
+    /// Creates an empty registry, whose sessions end `lifetime` after they started.
+    pub fn new(lifetime: Duration) -> Self {
+        Sessions { sessions: Mutex::new(HashMap::new()), lifetime }
+    }
+
+    /// Starts a session for `subject` with the session id `sid` of the provider and returns its random id.
+    pub fn start(&self, subject: &str, sid: Option<&str>) -> String {
+        let id = CsrfToken::new_random().secret().to_string();
+        let now = Instant::now();
+        let session = Session {
+            subject: subject.to_string(),
+            sid: sid.map(str::to_string),
+            expires: now + self.lifetime,
+        };
+
+        let mut sessions = self.sessions.lock().expect("Session registry is not poisoned");
+        sessions.retain(|_, session| session.expires > now);
+        sessions.insert(id.clone(), session);
+        id
+    }
+
+    /// The session with `id`, if it did neither end nor expire.
+    pub fn get(&self, id: &str) -> Option<Session> {
+        let sessions = self.sessions.lock().expect("Session registry is not poisoned");
+        sessions.get(id)
+            .filter(|session| session.expires > Instant::now())
+            .cloned()
+    }
+
+    /// Ends the session with `id`.
+    pub fn end(&self, id: &str) {
+        self.sessions.lock().expect("Session registry is not poisoned").remove(id);
+    }
+
+    /// Ends the sessions the provider names by `subject` and `sid`. With a `sid`, only the sessions with
+    /// that `sid` end, and of these only the ones of `subject`, if it is given as well. With only a
+    /// `subject`, all sessions of the user end. Returns how many sessions ended.
+    pub fn revoke(&self, subject: Option<&str>, sid: Option<&str>) -> usize {
+        if subject.is_none() && sid.is_none() {
+            return 0;
+        }
+
+        let mut sessions = self.sessions.lock().expect("Session registry is not poisoned");
+        let before = sessions.len();
+        sessions.retain(|_, session| {
+            let matches = subject.is_none_or(|subject| session.subject == subject)
+                && sid.is_none_or(|sid| session.sid.as_deref() == Some(sid));
+            !matches
+        });
+        before - sessions.len()
+    }
+
+    /// The number of sessions, including the ones that expired but were not removed yet.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().expect("Session registry is not poisoned").len()
+    }
+
+    /// Whether there are no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,208 @@
+//! Logins that were started at a provider, but not yet completed. The hatches that redirect to a provider,
+//! the OpenID Connect and OAuth2 hatches, keep the nonce and PKCE code verifier of a login in a [`StateStore`], under the random `state` the provider
+//! returns with its callback. Every login has its own entry, so users can log in in several tabs at
+//! once, and an entry can only be taken once and only until it expires. A callback whose `state` is not
+//! in the store, or was started by another browser or hatch, is rejected with `400 Bad Request`.
+//!
+//! The hatches use a [`MemoryStateStore`], unless a [`LoginStates`] is managed by rocket, e.g. to share
+//! pending logins between several instances of an app:
+//!
+//! ```rust,ignore
+//! rocket::build()
+//!     .manage(LoginStates::new(RedisStateStore::new("redis://localhost")))
+//!     .attach(Airlock::<OidcHatch>::fairing())
+//! ```
+//!
+//! Only available with the `oidc` or `oauth2` feature.
+
+use std::{collections::{BTreeSet, HashMap}, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use rocket::{Build, Rocket};
+use serde::{Deserialize, Serialize};
+
+
+/// Errors of a [`StateStore`], e.g. if it can not reach its database.
+pub type StateStoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A login that was started at a provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginState {
+    /// The instance of the hatch that started the login.
+    pub hatch: String,
+    /// The random id of the browser that started the login, which is also kept in a private cookie.
+    pub browser: String,
+    /// The nonce of an OpenID Connect login.
+    pub nonce: Option<String>,
+    /// The PKCE code verifier, unless PKCE is disabled.
+    pub pkce_verifier: Option<String>,
+    /// The uri the callback redirects to after the login, which was checked against the [`ReturnTo`](crate::ReturnTo) of the hatch.
+    pub return_to: Option<String>,
+}
+
+/// Storage of the logins that were started at a provider, by their `state`.
+#[rocket::async_trait]
+pub trait StateStore: Send + Sync + 'static {
+    /// Stores `login` under `state`, until it is taken or `ttl` passed.
+    async fn insert(&self, state: String, login: LoginState, ttl: Duration) -> Result<(), StateStoreError>;
+
+    /// Removes the login stored under `state` and returns it, unless it expired.
+    async fn take(&self, state: &str) -> Result<Option<LoginState>, StateStoreError>;
+}
+
+/// A [`StateStore`] in memory, which holds a limited number of logins. Expired logins are dropped whenever
+/// a login is stored, and if the store is still full, the logins that expire first are dropped.
+pub struct MemoryStateStore {
+    logins: Mutex<Logins>,
+    capacity: usize,
+}
+
+/// The logins of a [`MemoryStateStore`], by their `state` and ordered by when they expire.
+#[derive(Default)]
+struct Logins {
+    by_state: HashMap<String, (Instant, LoginState)>,
+    by_expiry: BTreeSet<(Instant, String)>,
+}
+
+impl Logins {
+    fn remove(&mut self, state: &str) -> Option<(Instant, LoginState)> {
+        let (expires, login) = self.by_state.remove(state)?;
+        self.by_expiry.remove(&(expires, state.to_string()));
+        Some((expires, login))
+    }
+
+    /// Removes the login that expires first, if it expires before `until`.
+    fn pop_first(&mut self, until: Option<Instant>) -> bool {
+        let Some((expires, state)) = self.by_expiry.first().cloned() else {
+            return false;
+        };
+        if until.is_some_and(|until| expires > until) {
+            return false;
+        }
+        self.remove(&state).is_some()
+    }
+}
+
+impl MemoryStateStore {
+    /// Creates a store for at most `capacity` logins.
+    pub fn new(capacity: usize) -> Self {
+        MemoryStateStore { logins: Mutex::new(Logins::default()), capacity }
+    }
+}
+
+impl Default for MemoryStateStore {
+    fn default() -> Self {
+        MemoryStateStore::new(10_000)
+    }
+}
+
+#[rocket::async_trait]
+impl StateStore for MemoryStateStore {
+    async fn insert(&self, state: String, login: LoginState, ttl: Duration) -> Result<(), StateStoreError> {
+        let now = Instant::now();
+        let mut logins = self.logins.lock().expect("Login states are not poisoned");
+        logins.remove(&state);
+        while logins.pop_first(Some(now)) {}
+        while logins.by_state.len() >= self.capacity.max(1) && logins.pop_first(None) {}
+        logins.by_expiry.insert((now + ttl, state.clone()));
+        logins.by_state.insert(state, (now + ttl, login));
+        Ok(())
+    }
+
+    async fn take(&self, state: &str) -> Result<Option<LoginState>, StateStoreError> {
+        let mut logins = self.logins.lock().expect("Login states are not poisoned");
+        Ok(logins.remove(state)
+            .filter(|(expires, _)| *expires > Instant::now())
+            .map(|(_, login)| login))
+    }
+}
+
+/// The [`StateStore`] of the hatches, which can be managed by rocket to replace the default [`MemoryStateStore`].
+#[derive(Clone)]
+pub struct LoginStates(Arc<dyn StateStore>);
+
+impl LoginStates {
+    /// Creates the login states, which are kept in `store`.
+    pub fn new(store: impl StateStore) -> Self {
+        LoginStates(Arc::new(store))
+    }
+
+    /// The login states managed by `rocket`, or new ones in memory.
+    pub(crate) fn of(rocket: &Rocket<Build>) -> Self {
+        rocket.state::<LoginStates>()
+            .cloned()
+            .unwrap_or_else(|| LoginStates::new(MemoryStateStore::default()))
+    }
+
+    /// The store of the login states.
+    pub fn store(&self) -> &dyn StateStore {
+        &*self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn login(hatch: &str) -> LoginState {
+        LoginState { hatch: hatch.into(), browser: "browser".into(), nonce: None, pkce_verifier: None, return_to: None }
+    }
+
+    async fn taken(store: &MemoryStateStore, state: &str) -> Option<String> {
+        store.take(state).await.unwrap().map(|login| login.hatch)
+    }
+
+    #[rocket::async_test]
+    async fn logins_are_taken_once() {
+        let store = MemoryStateStore::default();
+        store.insert("a".into(), login("first"), MINUTE).await.unwrap();
+        store.insert("b".into(), login("second"), MINUTE).await.unwrap();
+
+        assert_eq!(taken(&store, "a").await.as_deref(), Some("first"));
+        assert_eq!(taken(&store, "a").await, None);
+        assert_eq!(taken(&store, "b").await.as_deref(), Some("second"));
+        assert_eq!(taken(&store, "unknown").await, None);
+    }
+
+    #[rocket::async_test]
+    async fn expired_logins_are_not_taken() {
+        let store = MemoryStateStore::default();
+        store.insert("a".into(), login("first"), Duration::ZERO).await.unwrap();
+        assert_eq!(taken(&store, "a").await, None);
+    }
+
+    #[rocket::async_test]
+    async fn expired_logins_are_dropped_first() {
+        let store = MemoryStateStore::new(2);
+        store.insert("expired".into(), login("expired"), Duration::ZERO).await.unwrap();
+        store.insert("a".into(), login("first"), MINUTE).await.unwrap();
+        assert_eq!(store.logins.lock().unwrap().by_state.len(), 1);
+
+        store.insert("b".into(), login("second"), MINUTE).await.unwrap();
+        assert_eq!(taken(&store, "a").await.as_deref(), Some("first"));
+        assert_eq!(taken(&store, "b").await.as_deref(), Some("second"));
+    }
+
+    #[rocket::async_test]
+    async fn full_store_drops_the_logins_that_expire_first() {
+        let store = MemoryStateStore::new(2);
+        store.insert("a".into(), login("first"), 2 * MINUTE).await.unwrap();
+        store.insert("b".into(), login("second"), MINUTE).await.unwrap();
+        store.insert("c".into(), login("third"), 3 * MINUTE).await.unwrap();
+
+        assert_eq!(taken(&store, "b").await, None);
+        assert_eq!(taken(&store, "a").await.as_deref(), Some("first"));
+        assert_eq!(taken(&store, "c").await.as_deref(), Some("third"));
+        let logins = store.logins.lock().unwrap();
+        assert!(logins.by_state.is_empty() && logins.by_expiry.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn login_under_the_same_state_is_replaced() {
+        let store = MemoryStateStore::new(2);
+        store.insert("a".into(), login("first"), MINUTE).await.unwrap();
+        store.insert("a".into(), login("second"), MINUTE).await.unwrap();
+        assert_eq!(store.logins.lock().unwrap().by_expiry.len(), 1);
+        assert_eq!(taken(&store, "a").await.as_deref(), Some("second"));
+    }
+}
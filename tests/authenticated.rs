@@ -0,0 +1,111 @@
+mod common;
+
+use rocket::{
+    get, post, routes, Build, Rocket,
+    http::{Header, Status},
+    local::asynchronous::{Client, LocalResponse},
+};
+use std::sync::atomic::Ordering;
+use rocket_airlock::{Airlock, Authenticated, Forward, Guarded, RedirectToLogin, Reject, bulkhead::HasRole, label};
+use common::HeaderHatch;
+
+#[get("/forward")]
+fn forward(user: Authenticated<HeaderHatch, Forward>) -> String {
+    user.name.clone()
+}
+
+#[get("/forward", rank = 2)]
+fn anonymous() -> &'static str {
+    "anonymous"
+}
+
+#[get("/reject")]
+fn reject(user: Authenticated<HeaderHatch, Reject>) -> String {
+    user.name.clone()
+}
+
+#[get("/redirect?<page>")]
+fn redirect(user: Authenticated<HeaderHatch, RedirectToLogin>, page: Option<u8>) -> String {
+    format!("{} {:?}", user.name, page)
+}
+
+#[post("/redirect")]
+fn redirect_post(user: Authenticated<HeaderHatch, RedirectToLogin>) -> String {
+    user.name.clone()
+}
+
+label!(Admin = "admin");
+
+#[get("/authentications")]
+fn authentications(
+    user: Authenticated<HeaderHatch>,
+    rejecting: Authenticated<HeaderHatch, Reject>,
+    admin: Guarded<HeaderHatch, HasRole<Admin>>,
+    airlock: Airlock<HeaderHatch>,
+) -> String {
+    let authentications = airlock.hatch.authentications.load(Ordering::SeqCst);
+    format!("{} {} {} {}", user.name, rejecting.name, admin.name, authentications)
+}
+
+fn rocket() -> Rocket<Build> {
+    rocket::build()
+        .mount("/", routes![forward, anonymous, reject, redirect, redirect_post, authentications])
+        .attach(Airlock::<HeaderHatch>::fairing().mount("/auth"))
+}
+
+fn alice() -> Header<'static> {
+    Header::new("X-User", "alice")
+}
+
+async fn body(response: LocalResponse<'_>) -> String {
+    response.into_string().await.unwrap()
+}
+
+#[rocket::async_test]
+async fn forward_falls_through_to_the_next_route() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    assert_eq!(body(client.get("/forward").header(alice()).dispatch().await).await, "alice");
+
+    let response = client.get("/forward").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(body(response).await, "anonymous");
+}
+
+#[rocket::async_test]
+async fn reject_answers_with_the_challenge() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    assert_eq!(body(client.get("/reject").header(alice()).dispatch().await).await, "alice");
+
+    let response = client.get("/reject").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(response.headers().get_one("WWW-Authenticate"), Some(r#"Header realm="tests""#));
+    assert_eq!(response.headers().get_one("Location"), None);
+}
+
+#[rocket::async_test]
+async fn redirect_to_login_returns_to_the_request() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    assert_eq!(body(client.get("/redirect").header(alice()).dispatch().await).await, "alice None");
+
+    let response = client.get("/redirect?page=2").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/auth/login?return_to=%2Fredirect%3Fpage%3D2"));
+    assert_eq!(response.headers().get_one("WWW-Authenticate"), None);
+    assert_eq!(body(client.get("/auth/login").dispatch().await).await, "login");
+}
+
+#[rocket::async_test]
+async fn redirect_to_login_only_returns_to_get_requests() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    let response = client.post("/redirect").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/auth/login"));
+}
+
+#[rocket::async_test]
+async fn requests_are_authenticated_once() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    let request = || client.get("/authentications").header(alice()).header(Header::new("X-Roles", "admin"));
+    assert_eq!(body(request().dispatch().await).await, "alice alice alice 1");
+    assert_eq!(body(request().dispatch().await).await, "alice alice alice 2");
+}
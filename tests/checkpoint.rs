@@ -0,0 +1,100 @@
+mod common;
+
+use rocket::{
+    get, routes, Build, Rocket,
+    figment::providers::{Format, Toml},
+    http::{Header, Status},
+    error::ErrorKind,
+    local::asynchronous::Client,
+};
+use rocket_airlock::{Airlock, Authenticated, Checkpoints, checkpoint::Lockdown};
+use common::HeaderHatch;
+
+#[get("/admin/secret")]
+fn secret() -> &'static str {
+    "secret"
+}
+
+#[get("/health")]
+fn health() -> &'static str {
+    "ok"
+}
+
+fn rocket() -> Rocket<Build> {
+    let figment = rocket::Config::figment()
+        .merge(Toml::string(r#"
+            [[default.airlock.routes]]
+            path = "/admin/**"
+            hatch = "header"
+
+            [default.airlock.lockdown]
+            mode = "reject"
+            public = ["/health"]
+        "#).nested());
+
+    rocket::custom(figment)
+        .mount("/", routes![secret, health])
+        .attach(Airlock::<HeaderHatch>::fairing())
+        .attach(Checkpoints::fairing())
+}
+
+async fn get(client: &Client, path: &str) -> Status {
+    client.get(path).dispatch().await.status()
+}
+
+#[rocket::async_test]
+async fn rule_protects_route() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    assert_eq!(get(&client, "/admin/secret").await, Status::Unauthorized);
+
+    let response = client.get("/admin/secret").header(Header::new("X-User", "alice")).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn encoded_path_does_not_bypass_rule() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    assert_eq!(get(&client, "/%61dmin/secret").await, Status::Unauthorized);
+    assert_eq!(get(&client, "/admin/%73ecret").await, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn empty_segments_do_not_bypass_rule() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    assert_eq!(get(&client, "//admin/secret").await, Status::Unauthorized);
+    assert_eq!(get(&client, "/admin//secret").await, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn public_paths_are_normalized() {
+    let client = Client::tracked(rocket()).await.unwrap();
+    assert_eq!(get(&client, "/health").await, Status::Ok);
+    assert_eq!(get(&client, "//%68ealth").await, Status::Ok);
+    assert_eq!(get(&client, "/unknown").await, Status::Unauthorized);
+}
+
+#[get("/profile")]
+fn profile(user: Authenticated<HeaderHatch>) -> String {
+    user.name.clone()
+}
+
+fn abort_rocket(config: &str) -> Rocket<Build> {
+    rocket::custom(rocket::Config::figment().merge(Toml::string(config).nested()))
+        .mount("/", routes![profile])
+        .attach(Airlock::<HeaderHatch>::fairing())
+        .attach(Checkpoints::fairing().lockdown(Lockdown::Abort))
+}
+
+#[rocket::async_test]
+async fn abort_lockdown_requires_rule_for_guarded_route() {
+    let error = abort_rocket("").ignite().await.unwrap_err();
+    assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)), "{:?}", error.kind());
+
+    let rule = r#"
+        [[default.airlock.routes]]
+        path = "/profile"
+        hatch = "header"
+    "#;
+    assert!(abort_rocket(rule).ignite().await.is_ok());
+    assert!(abort_rocket(r#"default.airlock.lockdown.public = ["/profile"]"#).ignite().await.is_ok());
+}
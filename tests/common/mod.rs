@@ -0,0 +1,79 @@
+#![allow(dead_code)]
+
+pub mod provider;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rocket::{get, routes, uri, Build, Request, Rocket, Route, http::uri::Origin};
+use rocket_airlock::{Airlock, Hatch, Mounted, bulkhead::Roles};
+
+/// A user, as authenticated by the `X-User` and `X-Roles` headers.
+pub struct User {
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+impl Roles for User {
+    fn roles(&self) -> &[String] {
+        &self.roles
+    }
+}
+
+/// A hatch that trusts the `X-User` header, so tests can decide who is authenticated. Its login route
+/// is `/login`, and it challenges with the `Header` scheme.
+#[derive(Default)]
+pub struct HeaderHatch {
+    /// How often a request was authenticated.
+    pub authentications: AtomicUsize,
+}
+
+#[rocket::async_trait]
+impl Hatch for HeaderHatch {
+    type Comm = ();
+    type Error = std::convert::Infallible;
+    type Principal = User;
+
+    fn comm(&self) -> &Self::Comm {
+        &()
+    }
+
+    fn name() -> &'static str {
+        "Header"
+    }
+
+    fn routes() -> Vec<Route> {
+        routes![base, login]
+    }
+
+    fn login_uri(&self) -> Option<Origin<'static>> {
+        Some(uri!(login))
+    }
+
+    fn challenge(&self) -> Option<String> {
+        Some("Header realm=\"tests\"".into())
+    }
+
+    async fn from(rocket: Rocket<Build>) -> rocket_airlock::Result<Self, Self::Error> {
+        Ok((rocket, HeaderHatch::default()))
+    }
+
+    async fn authenticate(&self, request: &Request<'_>) -> Option<Self::Principal> {
+        self.authentications.fetch_add(1, Ordering::SeqCst);
+        let name = request.headers().get_one("X-User")?.to_string();
+        let roles = request.headers().get_one("X-Roles")
+            .map(|roles| roles.split(',').map(String::from).collect())
+            .unwrap_or_default();
+        Some(User { name, roles })
+    }
+}
+
+/// Where the instance of the hatch that mounted this route lives.
+#[get("/base")]
+fn base(airlock: Airlock<HeaderHatch, Mounted>) -> String {
+    airlock.base().to_string()
+}
+
+#[get("/login")]
+fn login() -> &'static str {
+    "login"
+}
@@ -0,0 +1,318 @@
+//! A stub identity provider, that serves discovery, JWKS, token, userinfo and introspection endpoints on a random port.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use openidconnect::{JsonWebKeyId, PrivateSigningKey, core::CoreRsaPrivateSigningKey, url::Url};
+use rocket::{
+    get, post, routes, FromForm, Request, State,
+    config::LogLevel,
+    fairing::AdHoc,
+    form::Form,
+    http::{RawStr, Status},
+    request::{FromRequest, Outcome},
+    response::content::RawJson,
+    tokio::sync::oneshot,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+/// The signing keys of the provider, by their key id.
+const KEYS: [(&str, &str); 2] = [
+    ("first", include_str!("../keys/first.pem")),
+    ("second", include_str!("../keys/second.pem")),
+];
+
+pub const CLIENT_ID: &str = "client";
+/// Has characters that need to be form-urlencoded, when it is sent with HTTP Basic authentication.
+pub const CLIENT_SECRET: &str = "s3cr3t:+/&%";
+
+/// A pending authorization, which the client redeems with its code at the token endpoint.
+struct Grant {
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+}
+
+pub struct Provider {
+    issuer: OnceLock<String>,
+    /// The id of the key that signs tokens.
+    pub signing_key: Mutex<&'static str>,
+    /// The ids of the keys in the JWKS.
+    pub published_keys: Mutex<Vec<&'static str>>,
+    /// Whether the JWKS endpoint answers, or fails with `503 Service Unavailable`.
+    pub jwks_available: AtomicBool,
+    pub jwks_requests: AtomicUsize,
+    /// The `sub` of the ID tokens issued for a code, and for a refresh token.
+    pub subject: Mutex<String>,
+    pub refreshed_subject: Mutex<String>,
+    /// The `expires_in` of access tokens.
+    pub expires_in: AtomicU64,
+    pub refreshes: AtomicUsize,
+    /// The answers of the introspection endpoint, by token. Unknown tokens are inactive.
+    pub introspections: Mutex<HashMap<String, Value>>,
+    pub introspection_requests: AtomicUsize,
+    /// The claims the userinfo endpoint adds to the `sub` of the user.
+    pub userinfo: Mutex<Value>,
+    /// The access tokens that were issued, by the `sub` they were issued for.
+    access_tokens: Mutex<HashMap<String, String>>,
+    grants: Mutex<HashMap<String, Grant>>,
+}
+
+impl Provider {
+    /// Launches the provider on a random port and waits until it is in orbit.
+    pub async fn launch() -> Arc<Provider> {
+        let provider = Arc::new(Provider {
+            issuer: OnceLock::new(),
+            signing_key: Mutex::new("first"),
+            published_keys: Mutex::new(vec!["first"]),
+            jwks_available: AtomicBool::new(true),
+            jwks_requests: AtomicUsize::new(0),
+            subject: Mutex::new("alice".into()),
+            refreshed_subject: Mutex::new("alice".into()),
+            expires_in: AtomicU64::new(3600),
+            refreshes: AtomicUsize::new(0),
+            introspections: Mutex::new(HashMap::new()),
+            introspection_requests: AtomicUsize::new(0),
+            userinfo: Mutex::new(json!({})),
+            access_tokens: Mutex::new(HashMap::new()),
+            grants: Mutex::new(HashMap::new()),
+        });
+
+        let (tx, rx) = oneshot::channel();
+        let tx = Mutex::new(Some(tx));
+        let config = rocket::Config {
+            port: 0,
+            log_level: LogLevel::Off,
+            ..rocket::Config::debug_default()
+        };
+        let rocket = rocket::custom(config)
+            .mount("/", routes![discovery, jwks, token, userinfo, introspect])
+            .manage(provider.clone())
+            .attach(AdHoc::on_liftoff("Issuer", move |rocket| Box::pin(async move {
+                let provider = rocket.state::<Arc<Provider>>().unwrap();
+                let _ = provider.issuer.set(format!("http://127.0.0.1:{}", rocket.config().port));
+                if let Some(tx) = tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+            })));
+        rocket::tokio::spawn(rocket.launch());
+        rx.await.expect("Provider lifts off");
+        provider
+    }
+
+    pub fn issuer(&self) -> &str {
+        self.issuer.get().expect("Provider is in orbit")
+    }
+
+    /// Signs `claims` with the current signing key.
+    pub fn sign(&self, claims: &Value) -> String {
+        self.sign_with(*self.signing_key.lock().unwrap(), claims)
+    }
+
+    /// Signs `claims` with the key `kid`, which does not need to be published.
+    pub fn sign_with(&self, kid: &str, claims: &Value) -> String {
+        let (_, pem) = KEYS.iter().find(|(id, _)| *id == kid).expect("Known key");
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(kid.to_string());
+        jsonwebtoken::encode(&header, claims, &EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap()).unwrap()
+    }
+
+    /// Lets the user authorize the login the client redirected to, as `location`. Returns the path of
+    /// the callback, with which the provider redirects back to the client.
+    pub fn authorize(&self, location: &str) -> String {
+        let url = Url::parse(location).expect("Absolute authorization url");
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        let redirect = Url::parse(&query["redirect_uri"]).expect("Absolute redirect url");
+        let code = format!("code-{}", self.grants.lock().unwrap().len());
+        self.grants.lock().unwrap().insert(code.clone(), Grant {
+            nonce: query.get("nonce").cloned(),
+            code_challenge: query.get("code_challenge").cloned(),
+        });
+        format!("{}?code={}&state={}", redirect.path(), code, RawStr::new(&query["state"]).percent_encode())
+    }
+
+    /// The claims of an ID token for `sub`.
+    pub fn id_token_claims(&self, sub: &str, nonce: Option<&str>) -> Value {
+        let now = now();
+        let mut claims = json!({
+            "iss": self.issuer(),
+            "aud": CLIENT_ID,
+            "sub": sub,
+            "sid": format!("sid-{}", sub),
+            "preferred_username": sub,
+            "iat": now,
+            "exp": now + 300,
+        });
+        if let Some(nonce) = nonce {
+            claims["nonce"] = nonce.into();
+        }
+        claims
+    }
+
+    /// The claims of a valid logout token for the session of `sub`.
+    pub fn logout_token_claims(&self, sub: &str) -> Value {
+        let now = now();
+        json!({
+            "iss": self.issuer(),
+            "aud": CLIENT_ID,
+            "iat": now,
+            "exp": now + 60,
+            "jti": format!("jti-{}", now),
+            "sub": sub,
+            "sid": format!("sid-{}", sub),
+            "events": { "http://schemas.openid.net/event/backchannel-logout": {} },
+        })
+    }
+
+    fn tokens(&self, sub: &str, nonce: Option<&str>) -> RawJson<String> {
+        let refreshes = self.refreshes.load(Ordering::SeqCst);
+        let access_token = format!("access-{}", refreshes);
+        self.access_tokens.lock().unwrap().insert(access_token.clone(), sub.to_string());
+        RawJson(json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "expires_in": self.expires_in.load(Ordering::SeqCst),
+            "refresh_token": format!("refresh-{}", refreshes),
+            "id_token": self.sign(&self.id_token_claims(sub, nonce)),
+        }).to_string())
+    }
+}
+
+/// Seconds since the unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[get("/.well-known/openid-configuration")]
+fn discovery(provider: &State<Arc<Provider>>) -> RawJson<String> {
+    let issuer = provider.issuer();
+    RawJson(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "jwks_uri": format!("{}/jwks", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+        "end_session_endpoint": format!("{}/logout", issuer),
+        "introspection_endpoint": format!("{}/introspect", issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }).to_string())
+}
+
+#[get("/jwks")]
+fn jwks(provider: &State<Arc<Provider>>) -> Result<RawJson<String>, Status> {
+    provider.jwks_requests.fetch_add(1, Ordering::SeqCst);
+    if !provider.jwks_available.load(Ordering::SeqCst) {
+        return Err(Status::ServiceUnavailable);
+    }
+
+    let keys: Vec<_> = provider.published_keys.lock().unwrap().iter()
+        .map(|kid| {
+            let (_, pem) = KEYS.iter().find(|(id, _)| id == kid).expect("Known key");
+            let key = CoreRsaPrivateSigningKey::from_pem(pem, Some(JsonWebKeyId::new(kid.to_string()))).unwrap();
+            serde_json::to_value(key.as_verification_key()).unwrap()
+        })
+        .collect();
+    Ok(RawJson(json!({ "keys": keys }).to_string()))
+}
+
+#[derive(FromForm)]
+struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+}
+
+#[post("/token", data = "<form>")]
+fn token(provider: &State<Arc<Provider>>, form: Form<TokenRequest>) -> Result<RawJson<String>, Status> {
+    match form.grant_type.as_str() {
+        "authorization_code" => {
+            let code = form.code.as_deref().ok_or(Status::BadRequest)?;
+            let grant = provider.grants.lock().unwrap().remove(code).ok_or(Status::BadRequest)?;
+            if let Some(challenge) = grant.code_challenge {
+                let verifier = form.code_verifier.as_deref().ok_or(Status::BadRequest)?;
+                if URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) != challenge {
+                    return Err(Status::BadRequest);
+                }
+            }
+            let subject = provider.subject.lock().unwrap().clone();
+            Ok(provider.tokens(&subject, grant.nonce.as_deref()))
+        },
+        "refresh_token" => {
+            form.refresh_token.as_deref().ok_or(Status::BadRequest)?;
+            provider.refreshes.fetch_add(1, Ordering::SeqCst);
+            let subject = provider.refreshed_subject.lock().unwrap().clone();
+            Ok(provider.tokens(&subject, None))
+        },
+        _ => Err(Status::BadRequest),
+    }
+}
+
+/// The access token of a request, from its `Authorization: Bearer` header.
+struct BearerToken(String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        match request.headers().get_one("Authorization").and_then(|header| header.strip_prefix("Bearer ")) {
+            Some(token) => Outcome::Success(BearerToken(token.to_string())),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[get("/userinfo")]
+fn userinfo(provider: &State<Arc<Provider>>, token: BearerToken) -> Result<RawJson<String>, Status> {
+    let sub = provider.access_tokens.lock().unwrap().get(&token.0).cloned().ok_or(Status::Unauthorized)?;
+    let mut userinfo = provider.userinfo.lock().unwrap().clone();
+    userinfo["sub"] = sub.into();
+    Ok(RawJson(userinfo.to_string()))
+}
+
+/// The client credentials of a request, from its `Authorization: Basic` header. They are form-urlencoded
+/// before they are encoded with base64, see RFC 6749, section 2.3.1.
+struct ClientCredentials(String, String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientCredentials {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let credentials = request.headers().get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Basic "))
+            .and_then(|credentials| STANDARD.decode(credentials).ok())
+            .and_then(|credentials| String::from_utf8(credentials).ok())
+            .and_then(|credentials| {
+                let (id, secret) = credentials.split_once(':')?;
+                let decode = |s: &str| RawStr::new(s).url_decode().ok().map(|s| s.into_owned());
+                Some(ClientCredentials(decode(id)?, decode(secret)?))
+            });
+        match credentials {
+            Some(credentials) => Outcome::Success(credentials),
+            None => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[derive(FromForm)]
+struct IntrospectionRequest {
+    token: String,
+}
+
+#[post("/introspect", data = "<form>")]
+fn introspect(provider: &State<Arc<Provider>>, client: ClientCredentials, form: Form<IntrospectionRequest>) -> Result<RawJson<String>, Status> {
+    provider.introspection_requests.fetch_add(1, Ordering::SeqCst);
+    if client.0 != CLIENT_ID || client.1 != CLIENT_SECRET {
+        return Err(Status::Unauthorized);
+    }
+    let answer = provider.introspections.lock().unwrap().get(&form.token).cloned();
+    Ok(RawJson(answer.unwrap_or_else(|| json!({ "active": false })).to_string()))
+}
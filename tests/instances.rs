@@ -0,0 +1,110 @@
+use std::sync::{Arc, Mutex};
+use rocket::{
+    get, routes, Build, Request, Rocket, Route,
+    fairing::AdHoc,
+    figment::providers::{Format, Toml},
+    error::ErrorKind,
+    http::Status,
+    local::asynchronous::Client,
+};
+use rocket_airlock::{Airlock, Authenticated, Hatch, Reject, label};
+
+/// A hatch that authenticates nobody, and challenges with the realm it is configured for.
+struct RealmHatch {
+    realm: String,
+}
+
+#[rocket::async_trait]
+impl Hatch for RealmHatch {
+    type Comm = ();
+    type Error = rocket::figment::Error;
+    type Principal = ();
+
+    fn comm(&self) -> &Self::Comm {
+        &()
+    }
+
+    fn name() -> &'static str {
+        "Realm"
+    }
+
+    fn routes() -> Vec<Route> {
+        Vec::new()
+    }
+
+    fn challenge(&self) -> Option<String> {
+        Some(format!("Basic realm=\"{}\"", self.realm))
+    }
+
+    async fn from(rocket: Rocket<Build>) -> rocket_airlock::Result<Self, Self::Error> {
+        match rocket.figment().extract_inner("airlock.realm.realm") {
+            Ok(realm) => Ok((rocket, RealmHatch { realm })),
+            Err(e) => Err((rocket, e)),
+        }
+    }
+
+    async fn authenticate(&self, _request: &Request<'_>) -> Option<Self::Principal> {
+        None
+    }
+}
+
+label!(Staff = "staff");
+label!(Guests = "guests");
+
+#[get("/staff")]
+fn staff(_user: Authenticated<RealmHatch, Reject, Staff>) {}
+
+#[get("/guests")]
+fn guests(_user: Authenticated<RealmHatch, Reject, Guests>) {}
+
+fn figment(config: &str) -> rocket::figment::Figment {
+    rocket::Config::figment().merge(Toml::string(config).nested())
+}
+
+#[rocket::async_test]
+async fn instances_are_configured_by_their_own_table() {
+    let config = r#"
+        [default.airlock.realm]
+        realm = "primary"
+
+        [default.airlock.staff]
+        realm = "staff"
+
+        [default.airlock.guests]
+        realm = "guests"
+    "#;
+    let rocket = rocket::custom(figment(config))
+        .mount("/", routes![staff, guests])
+        .attach(Airlock::<RealmHatch, Staff>::fairing())
+        .attach(Airlock::<RealmHatch, Guests>::fairing());
+    let client = Client::tracked(rocket).await.unwrap();
+
+    for (path, realm) in [("/staff", "staff"), ("/guests", "guests")] {
+        let response = client.get(path).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let challenge = format!("Basic realm=\"{}\"", realm);
+        assert_eq!(response.headers().get_one("WWW-Authenticate"), Some(challenge.as_str()));
+    }
+}
+
+#[rocket::async_test]
+async fn failed_instances_restore_the_config() {
+    let config = r#"
+        [default.airlock.realm]
+        realm = "primary"
+    "#;
+    let seen = Arc::new(Mutex::new(None));
+    let record = seen.clone();
+    let rocket = rocket::custom(figment(config))
+        .attach(Airlock::<RealmHatch, Staff>::fairing())
+        .attach(AdHoc::on_ignite("Record realm", |rocket| async move {
+            *record.lock().unwrap() = rocket.figment().extract_inner::<String>("airlock.realm.realm").ok();
+            rocket
+        }));
+
+    match rocket.ignite().await {
+        Ok(_) => panic!("the staff instance has no config"),
+        Err(e) => assert!(matches!(e.kind(), ErrorKind::FailedFairings(_))),
+    }
+    assert_eq!(seen.lock().unwrap().as_deref(), Some("primary"));
+}
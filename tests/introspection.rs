@@ -0,0 +1,134 @@
+#![cfg(feature = "introspection")]
+
+mod common;
+
+use std::sync::atomic::Ordering;
+use rocket::{
+    get, routes, Build, Rocket,
+    figment::providers::{Format, Toml},
+    http::{Header, Status},
+    local::asynchronous::Client,
+};
+use rocket_airlock::{Airlock, Authenticated, Reject, introspection::IntrospectionHatch};
+use serde_json::json;
+use common::provider::{now, Provider, CLIENT_ID, CLIENT_SECRET};
+
+#[get("/api")]
+fn api(token: Authenticated<IntrospectionHatch, Reject>) -> String {
+    token.sub.clone().unwrap_or_default()
+}
+
+/// The app, with the hatch configured for `provider` and the additional `config`.
+fn app(provider: &Provider, config: &str) -> Rocket<Build> {
+    let hatch = format!(r#"
+        [default.airlock.introspection]
+        introspection_url = "{}/introspect"
+        client_id = "{}"
+        client_secret = "{}"
+    "#, provider.issuer(), CLIENT_ID, CLIENT_SECRET);
+    let figment = rocket::Config::figment()
+        .merge(Toml::string(&hatch).nested())
+        .merge(Toml::string(config).nested());
+    rocket::custom(figment)
+        .mount("/", routes![api])
+        .attach(Airlock::<IntrospectionHatch>::fairing())
+}
+
+async fn request(client: &Client, token: &str) -> Status {
+    client.get("/api")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch().await
+        .status()
+}
+
+fn activate(provider: &Provider, token: &str, exp: u64) {
+    let answer = json!({ "active": true, "sub": "alice", "exp": exp });
+    provider.introspections.lock().unwrap().insert(token.into(), answer);
+}
+
+fn introspections(provider: &Provider) -> usize {
+    provider.introspection_requests.load(Ordering::SeqCst)
+}
+
+#[rocket::async_test]
+async fn active_tokens_are_accepted_and_cached() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+    activate(&provider, "active", now() + 3600);
+
+    let response = client.get("/api").header(Header::new("Authorization", "Bearer active")).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), "alice");
+    assert_eq!(request(&client, "active").await, Status::Ok);
+    assert_eq!(introspections(&provider), 1);
+}
+
+#[rocket::async_test]
+async fn inactive_tokens_are_rejected_and_cached() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+    provider.introspections.lock().unwrap().insert("revoked".into(), json!({ "active": false }));
+
+    assert_eq!(request(&client, "revoked").await, Status::Unauthorized);
+    assert_eq!(request(&client, "unknown").await, Status::Unauthorized);
+    assert_eq!(request(&client, "revoked").await, Status::Unauthorized);
+    assert_eq!(introspections(&provider), 2);
+}
+
+#[rocket::async_test]
+async fn caching_can_be_disabled() {
+    let provider = Provider::launch().await;
+    let config = r#"
+        [default.airlock.introspection]
+        cache_ttl = 0
+        negative_ttl = 0
+    "#;
+    let client = Client::tracked(app(&provider, config)).await.unwrap();
+    activate(&provider, "active", now() + 3600);
+
+    for _ in 0..2 {
+        assert_eq!(request(&client, "active").await, Status::Ok);
+        assert_eq!(request(&client, "unknown").await, Status::Unauthorized);
+    }
+    assert_eq!(introspections(&provider), 4);
+}
+
+#[rocket::async_test]
+async fn tokens_are_not_cached_beyond_their_expiry() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+    activate(&provider, "expiring", now());
+
+    assert_eq!(request(&client, "expiring").await, Status::Ok);
+    assert_eq!(request(&client, "expiring").await, Status::Ok);
+    assert_eq!(introspections(&provider), 2);
+}
+
+#[rocket::async_test]
+async fn full_cache_evicts_tokens() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "[default.airlock.introspection]\ncache_size = 1")).await.unwrap();
+    activate(&provider, "first", now() + 3600);
+    activate(&provider, "second", now() + 3600);
+
+    assert_eq!(request(&client, "first").await, Status::Ok);
+    assert_eq!(request(&client, "second").await, Status::Ok);
+    assert_eq!(request(&client, "second").await, Status::Ok);
+    assert_eq!(request(&client, "first").await, Status::Ok);
+    assert_eq!(introspections(&provider), 3);
+}
+
+#[rocket::async_test]
+async fn failed_introspections_are_not_cached() {
+    let provider = Provider::launch().await;
+    let config = r#"
+        [default.airlock.introspection]
+        client_secret = "wrong"
+    "#;
+    let client = Client::tracked(app(&provider, config)).await.unwrap();
+    activate(&provider, "active", now() + 3600);
+
+    assert_eq!(request(&client, "active").await, Status::Unauthorized);
+    assert_eq!(request(&client, "active").await, Status::Unauthorized);
+    assert_eq!(introspections(&provider), 2);
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "jwt")]
+
+mod common;
+
+use std::{sync::atomic::Ordering, time::Duration};
+use rocket::{
+    get, routes, Build, Rocket,
+    figment::providers::{Format, Toml},
+    http::{Header, Status},
+    local::asynchronous::Client,
+};
+use rocket_airlock::{Airlock, Authenticated, Communicator, Reject, jwt::{Jwks, JwksSource, JwtHatch}};
+use serde::Deserialize;
+use common::provider::Provider;
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+}
+
+#[get("/api")]
+fn api(claims: Authenticated<JwtHatch<Claims>, Reject>) -> String {
+    claims.sub.clone()
+}
+
+fn app(provider: &Provider) -> Rocket<Build> {
+    let hatch = format!(r#"
+        [default.airlock.jwt]
+        jwks_url = "{}/jwks"
+        issuer = "{}"
+    "#, provider.issuer(), provider.issuer());
+    let figment = rocket::Config::figment().merge(Toml::string(&hatch).nested());
+    rocket::custom(figment)
+        .mount("/", routes![api])
+        .attach(Airlock::<JwtHatch<Claims>>::fairing())
+}
+
+async fn request(client: &Client, token: &str) -> Status {
+    client.get("/api")
+        .header(Header::new("Authorization", format!("Bearer {}", token)))
+        .dispatch().await
+        .status()
+}
+
+#[rocket::async_test]
+async fn keys_rotated_in_are_loaded() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider)).await.unwrap();
+    let claims = provider.id_token_claims("alice", None);
+    assert_eq!(request(&client, &provider.sign(&claims)).await, Status::Ok);
+    assert_eq!(provider.jwks_requests.load(Ordering::SeqCst), 1);
+
+    provider.published_keys.lock().unwrap().push("second");
+    *provider.signing_key.lock().unwrap() = "second";
+    assert_eq!(request(&client, &provider.sign(&claims)).await, Status::Ok);
+    assert_eq!(provider.jwks_requests.load(Ordering::SeqCst), 2);
+    assert_eq!(request(&client, &provider.sign_with("first", &claims)).await, Status::Ok);
+    assert_eq!(provider.jwks_requests.load(Ordering::SeqCst), 2);
+}
+
+#[rocket::async_test]
+async fn tokens_of_unpublished_keys_are_rejected() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider)).await.unwrap();
+    let claims = provider.id_token_claims("alice", None);
+
+    assert_eq!(request(&client, &provider.sign_with("second", &claims)).await, Status::Unauthorized);
+    assert_eq!(provider.jwks_requests.load(Ordering::SeqCst), 2);
+}
+
+/// A JWKS of the provider, restored from a cache that was written while the provider was reachable.
+async fn restored(provider: &Provider) -> Jwks {
+    let port = provider.issuer().rsplit(':').next().unwrap();
+    let cache = std::env::temp_dir().join(format!("rocket_airlock-jwks-{}.json", port));
+    let source = || JwksSource::Url(format!("{}/jwks", provider.issuer()));
+    Jwks::load_or_restore(source(), cache.clone()).await.unwrap();
+    provider.jwks_available.store(false, Ordering::SeqCst);
+    let jwks = Jwks::load_or_restore(source(), cache.clone()).await.unwrap();
+    let _ = std::fs::remove_file(cache);
+    assert_eq!(jwks.len(), 1);
+    jwks
+}
+
+#[rocket::async_test]
+async fn restored_keys_are_refreshed_until_loaded() {
+    let provider = Provider::launch().await;
+    let jwks = restored(&provider).await;
+    assert_eq!(jwks.refresh_interval(), Some(Duration::from_secs(5 * 60)));
+
+    assert!(jwks.refresh().await.is_err());
+    assert_eq!(jwks.refresh_interval(), Some(Duration::from_secs(5 * 60)));
+
+    provider.jwks_available.store(true, Ordering::SeqCst);
+    jwks.refresh().await.unwrap();
+    assert_eq!(jwks.refresh_interval(), None);
+}
+
+#[rocket::async_test]
+async fn restored_keys_keep_the_configured_interval() {
+    let provider = Provider::launch().await;
+    let jwks = restored(&provider).await
+        .refresh_every(Duration::from_secs(60));
+    assert_eq!(jwks.refresh_interval(), Some(Duration::from_secs(60)));
+
+    provider.jwks_available.store(true, Ordering::SeqCst);
+    jwks.refresh().await.unwrap();
+    assert_eq!(jwks.refresh_interval(), Some(Duration::from_secs(60)));
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use rocket::{
+    Build, Rocket,
+    figment::providers::{Format, Toml},
+};
+use rocket_airlock::{Airlock, Hatch};
+use common::HeaderHatch;
+
+/// The URIs and ranks of the routes `rocket` mounted, by path.
+async fn routes(rocket: Rocket<Build>) -> Vec<(String, isize)> {
+    let rocket = rocket.ignite().await.unwrap();
+    let mut routes: Vec<_> = rocket.routes().map(|route| (route.uri.to_string(), route.rank)).collect();
+    routes.sort();
+    routes
+}
+
+/// The ranks the routes of the hatch have on their own, by path.
+fn ranks() -> Vec<isize> {
+    let mut routes: Vec<_> = HeaderHatch::routes().into_iter().map(|route| (route.uri.to_string(), route.rank)).collect();
+    routes.sort();
+    routes.into_iter().map(|(_, rank)| rank).collect()
+}
+
+#[rocket::async_test]
+async fn routes_are_mounted_at_the_base_with_the_rank_offset() {
+    let rocket = rocket::build()
+        .attach(Airlock::<HeaderHatch>::fairing().mount("/auth").rank(5));
+    let ranks = ranks();
+    assert_eq!(routes(rocket).await, vec![
+        ("/auth/base".to_string(), ranks[0] + 5),
+        ("/auth/login".to_string(), ranks[1] + 5),
+    ]);
+}
+
+#[rocket::async_test]
+async fn mount_config_overrides_the_fairing() {
+    let figment = rocket::Config::figment()
+        .merge(Toml::string(r#"
+            [default.airlock.header.mount]
+            base = "/configured"
+            rank = -3
+        "#).nested());
+    let rocket = rocket::custom(figment)
+        .attach(Airlock::<HeaderHatch>::fairing().mount("/auth").rank(5));
+    let ranks = ranks();
+    assert_eq!(routes(rocket).await, vec![
+        ("/configured/base".to_string(), ranks[0] - 3),
+        ("/configured/login".to_string(), ranks[1] - 3),
+    ]);
+}
+
+#[rocket::async_test]
+async fn ranks_stay_above_the_checkpoints() {
+    let rocket = rocket::build()
+        .attach(Airlock::<HeaderHatch>::fairing().rank(isize::MIN));
+    for (_, rank) in routes(rocket).await {
+        assert_eq!(rank, isize::MIN + 1);
+    }
+}
@@ -0,0 +1,136 @@
+#![cfg(feature = "oauth2")]
+
+mod common;
+
+use rocket::{
+    get, routes, Build, Rocket,
+    figment::providers::{Format, Toml},
+    http::Status,
+    local::asynchronous::{Client, LocalResponse},
+};
+use rocket_airlock::{Airlock, Authenticated, RedirectToLogin, oauth2::{Deserialized, OAuth2Hatch, OAuth2User}};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use common::provider::{Provider, CLIENT_ID, CLIENT_SECRET};
+
+/// A user that needs a `team` in the userinfo.
+#[derive(Serialize, Deserialize)]
+struct Member {
+    sub: String,
+    team: String,
+}
+
+type Hatch = OAuth2Hatch<Deserialized<OAuth2User>>;
+
+const SESSION: &str = "airlock_oauth2_user";
+
+#[get("/profile")]
+fn profile(user: Authenticated<Hatch, RedirectToLogin>) -> String {
+    format!("{} {}", user.subject, user.name.clone().unwrap_or_default())
+}
+
+#[get("/team")]
+fn team(member: Authenticated<OAuth2Hatch<Deserialized<Member>>, RedirectToLogin>) -> String {
+    member.team.clone()
+}
+
+fn figment(provider: &Provider) -> rocket::figment::Figment {
+    let hatch = format!(r#"
+        [default.airlock.oauth2]
+        auth_url = "{issuer}/authorize"
+        token_url = "{issuer}/token"
+        userinfo_url = "{issuer}/userinfo"
+        client_id = "{}"
+        client_secret = "{}"
+    "#, CLIENT_ID, CLIENT_SECRET, issuer = provider.issuer());
+    rocket::Config::figment().merge(Toml::string(&hatch).nested())
+}
+
+/// The app, with the hatch configured for `provider`.
+fn app(provider: &Provider) -> Rocket<Build> {
+    rocket::custom(figment(provider))
+        .mount("/", routes![profile])
+        .attach(Airlock::<Hatch>::fairing())
+}
+
+fn location(response: &LocalResponse<'_>) -> String {
+    response.headers().get_one("Location").expect("Redirect").to_string()
+}
+
+/// Starts a login at `path` and returns the url of the authorization endpoint it redirects to.
+async fn start_login(client: &Client, provider: &Provider, path: &str) -> String {
+    let response = client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let authorization = location(&response);
+    assert!(authorization.starts_with(&format!("{}/authorize?", provider.issuer())), "{}", authorization);
+    authorization
+}
+
+#[rocket::async_test]
+async fn login_maps_the_userinfo_to_the_session() {
+    let provider = Provider::launch().await;
+    *provider.userinfo.lock().unwrap() = json!({ "name": "Alice" });
+    let client = Client::tracked(app(&provider)).await.unwrap();
+
+    let response = client.get("/profile").dispatch().await;
+    assert_eq!(location(&response), "/login?return_to=%2Fprofile");
+
+    let authorization = start_login(&client, &provider, "/login?return_to=%2Fprofile").await;
+    let response = client.get(provider.authorize(&authorization)).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(location(&response), "/profile");
+    assert!(client.cookies().get_private(SESSION).is_some());
+
+    let response = client.get("/profile").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), "alice Alice");
+}
+
+#[rocket::async_test]
+async fn codes_need_the_pkce_verifier_of_their_login() {
+    let provider = Provider::launch().await;
+    let victim = Client::untracked(app(&provider)).await.unwrap();
+    let attacker = Client::tracked(app(&provider)).await.unwrap();
+
+    // The attacker injects the code of the victim into its own login, which has another verifier.
+    let stolen = provider.authorize(&start_login(&victim, &provider, "/login").await);
+    let own = provider.authorize(&start_login(&attacker, &provider, "/login").await);
+    let code = stolen.split('&').next().unwrap();
+    let state = own.split('&').nth(1).unwrap();
+    let response = attacker.get(format!("{}&{}", code, state)).dispatch().await;
+    assert_eq!(response.status(), Status::BadGateway);
+    assert!(attacker.cookies().get_private(SESSION).is_none());
+}
+
+#[rocket::async_test]
+async fn denied_logins_are_unauthorized() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider)).await.unwrap();
+
+    let authorization = start_login(&client, &provider, "/login").await;
+    let callback = provider.authorize(&authorization);
+    let state = callback.split('&').nth(1).unwrap();
+    let response = client.get(format!("/login?error=access_denied&{}", state)).dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(client.cookies().get_private(SESSION).is_none());
+}
+
+#[rocket::async_test]
+async fn failed_mappings_are_unauthorized() {
+    let provider = Provider::launch().await;
+    let rocket = rocket::custom(figment(&provider))
+        .mount("/", routes![team])
+        .attach(Airlock::<OAuth2Hatch<Deserialized<Member>>>::fairing());
+    let client = Client::tracked(rocket).await.unwrap();
+
+    let authorization = start_login(&client, &provider, "/login").await;
+    let response = client.get(provider.authorize(&authorization)).dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(client.cookies().get_private(SESSION).is_none());
+    assert_eq!(client.get("/team").dispatch().await.status(), Status::SeeOther);
+
+    *provider.userinfo.lock().unwrap() = json!({ "team": "core" });
+    let authorization = start_login(&client, &provider, "/login").await;
+    client.get(provider.authorize(&authorization)).dispatch().await;
+    assert_eq!(client.get("/team").dispatch().await.into_string().await.unwrap(), "core");
+}
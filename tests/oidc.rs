@@ -0,0 +1,405 @@
+#![cfg(feature = "oidc")]
+
+mod common;
+
+use std::{collections::HashMap, sync::{Arc, atomic::Ordering}};
+use rocket::{
+    get, routes, Build, Request, Rocket,
+    error::ErrorKind,
+    figment::providers::{Format, Toml},
+    http::{ContentType, Cookie, RawStr, Status},
+    local::asynchronous::{Client, LocalResponse},
+};
+use rocket_airlock::{Airlock, Authenticated, Breach, Bulkhead, Guarded, RedirectToLogin, oidc::{OidcHatch, OidcUser}};
+use serde_json::json;
+use common::provider::{now, Provider, CLIENT_ID, CLIENT_SECRET};
+
+#[get("/profile")]
+fn profile(user: Authenticated<OidcHatch, RedirectToLogin>) -> String {
+    user.subject.clone()
+}
+
+/// A policy that lets nobody pass.
+struct Nobody;
+
+#[rocket::async_trait]
+impl Bulkhead<OidcUser> for Nobody {
+    async fn inspect(_user: &OidcUser, _request: &Request<'_>) -> Result<(), Breach> {
+        Err(Breach::new("nobody may pass"))
+    }
+}
+
+#[get("/admin")]
+fn admin(_user: Guarded<OidcHatch, Nobody, RedirectToLogin>) {}
+
+/// The app, with the hatch configured for `provider` and the additional `config`.
+fn app(provider: &Provider, config: &str) -> Rocket<Build> {
+    let hatch = format!(r#"
+        [default.airlock.openidconnect]
+        discover_url = "{}"
+        client_id = "{}"
+        client_secret = "{}"
+    "#, provider.issuer(), CLIENT_ID, CLIENT_SECRET);
+    let figment = rocket::Config::figment()
+        .merge(Toml::string(&hatch).nested())
+        .merge(Toml::string(config).nested());
+    rocket::custom(figment)
+        .mount("/", routes![profile, admin])
+        .attach(Airlock::<OidcHatch>::fairing())
+}
+
+fn location(response: &LocalResponse<'_>) -> String {
+    response.headers().get_one("Location").expect("Redirect").to_string()
+}
+
+/// Logs in at the provider, starting at `path`, and returns where the callback redirects to.
+async fn login(client: &Client, provider: &Provider, path: &str) -> String {
+    let response = client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let authorization = location(&response);
+    assert!(authorization.starts_with(&format!("{}/authorize?", provider.issuer())), "{}", authorization);
+
+    let response = client.get(provider.authorize(&authorization)).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    location(&response)
+}
+
+#[rocket::async_test]
+async fn login_returns_to_the_requested_page() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+
+    let response = client.get("/profile").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(location(&response), "/login?return_to=%2Fprofile");
+
+    assert_eq!(login(&client, &provider, "/login?return_to=%2Fprofile").await, "/profile");
+    let response = client.get("/profile").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), "alice");
+}
+
+#[rocket::async_test]
+async fn callback_is_only_accepted_once() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+
+    let response = client.get("/login").dispatch().await;
+    let callback = provider.authorize(&location(&response));
+    assert_eq!(client.get(callback.clone()).dispatch().await.status(), Status::SeeOther);
+
+    let replayed = callback.replacen("code-0", "code-1", 1);
+    assert_eq!(client.get(replayed).dispatch().await.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn callback_with_unknown_state_is_rejected() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+
+    let response = client.get("/login").dispatch().await;
+    let callback = provider.authorize(&location(&response));
+    let forged = format!("{}&state=forged", callback.split("&state=").next().unwrap());
+    assert_eq!(client.get(forged).dispatch().await.status(), Status::BadRequest);
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::SeeOther);
+}
+
+#[rocket::async_test]
+async fn callback_of_another_browser_is_rejected() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+
+    let response = client.get("/login").dispatch().await;
+    let callback = provider.authorize(&location(&response));
+    let other_browser = client.get(callback).private_cookie(("airlock_openidconnect_login", "other"));
+    assert_eq!(other_browser.dispatch().await.status(), Status::BadRequest);
+}
+
+/// The `redirect_uri` the login sends to the provider.
+async fn redirect_uri(provider: &Arc<Provider>, config: &str) -> String {
+    let client = Client::tracked(app(provider, config)).await.unwrap();
+    let authorization = location(&client.get("/login").dispatch().await);
+    let url = openidconnect::url::Url::parse(&authorization).unwrap();
+    let (_, redirect_uri) = url.query_pairs().find(|(name, _)| name == "redirect_uri").unwrap();
+    redirect_uri.into_owned()
+}
+
+#[rocket::async_test]
+async fn redirect_url_is_derived_from_the_public_url() {
+    let provider = Provider::launch().await;
+    assert_eq!(redirect_uri(&provider, "").await, "http://127.0.0.1:8000/login");
+    assert_eq!(redirect_uri(&provider, r#"default.airlock.public_url = "https://App.example.com""#).await,
+        "https://app.example.com/login");
+
+    let relative = r#"
+        [default.airlock]
+        public_url = "https://app.example.com"
+        openidconnect.redirect_url = "/auth/callback"
+    "#;
+    assert_eq!(redirect_uri(&provider, relative).await, "https://app.example.com/auth/callback");
+}
+
+#[rocket::async_test]
+async fn redirect_url_is_required_on_unspecified_address() {
+    let provider = Provider::launch().await;
+    let error = app(&provider, r#"default.address = "0.0.0.0""#).ignite().await.unwrap_err();
+    assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)), "{:?}", error.kind());
+
+    let absolute = r#"
+        [default]
+        address = "0.0.0.0"
+        airlock.openidconnect.redirect_url = "https://app.example.com/login"
+    "#;
+    assert_eq!(redirect_uri(&provider, absolute).await, "https://app.example.com/login");
+}
+
+const BACKCHANNEL_LOGOUT: &str = "[default.airlock.openidconnect]\nbackchannel_logout = true";
+
+/// Sends the logout token `claims`, signed by the provider, to the back-channel logout route.
+async fn backchannel_logout(client: &Client, provider: &Provider, claims: &serde_json::Value) -> Status {
+    let response = client.post("/backchannel-logout")
+        .header(ContentType::Form)
+        .body(format!("logout_token={}", provider.sign(claims)))
+        .dispatch().await;
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+    response.status()
+}
+
+#[rocket::async_test]
+async fn backchannel_logout_ends_the_session() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, BACKCHANNEL_LOGOUT)).await.unwrap();
+    login(&client, &provider, "/login").await;
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::Ok);
+
+    let claims = provider.logout_token_claims("bob");
+    assert_eq!(backchannel_logout(&client, &provider, &claims).await, Status::Ok);
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::Ok);
+
+    let mut claims = provider.logout_token_claims("alice");
+    claims.as_object_mut().unwrap().remove("sub");
+    assert_eq!(backchannel_logout(&client, &provider, &claims).await, Status::Ok);
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::SeeOther);
+}
+
+#[rocket::async_test]
+async fn invalid_logout_tokens_are_rejected() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, BACKCHANNEL_LOGOUT)).await.unwrap();
+    login(&client, &provider, "/login").await;
+
+    let valid = provider.logout_token_claims("alice");
+    let mut other_event = valid.clone();
+    other_event["events"] = json!({ "http://schemas.openid.net/event/other": {} });
+    let mut no_events = valid.clone();
+    no_events.as_object_mut().unwrap().remove("events");
+    let mut nonce = valid.clone();
+    nonce["nonce"] = "n-0S6_WzA2Mj".into();
+    let mut anonymous = valid.clone();
+    anonymous.as_object_mut().unwrap().remove("sub");
+    anonymous.as_object_mut().unwrap().remove("sid");
+    let mut expired = valid.clone();
+    expired["exp"] = (now() - 3600).into();
+    let mut other_client = valid.clone();
+    other_client["aud"] = "other".into();
+
+    for claims in [other_event, no_events, nonce, anonymous, expired, other_client] {
+        assert_eq!(backchannel_logout(&client, &provider, &claims).await, Status::BadRequest, "{}", claims);
+    }
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn backchannel_logout_needs_to_be_enabled() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+    let claims = provider.logout_token_claims("alice");
+    assert_eq!(backchannel_logout(&client, &provider, &claims).await, Status::NotImplemented);
+}
+
+/// Logs in with an access token that expires within the `token_refresh_margin`, so every request refreshes it.
+async fn expiring_login(provider: &Provider) -> Client {
+    provider.expires_in.store(10, Ordering::SeqCst);
+    let client = Client::tracked(app(provider, "")).await.unwrap();
+    login(&client, provider, "/login").await;
+    client
+}
+
+#[rocket::async_test]
+async fn expiring_tokens_are_refreshed() {
+    let provider = Provider::launch().await;
+    let client = expiring_login(&provider).await;
+
+    let response = client.get("/profile").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), "alice");
+    assert_eq!(provider.refreshes.load(Ordering::SeqCst), 1);
+}
+
+#[rocket::async_test]
+async fn refreshed_tokens_are_kept_on_errors() {
+    let provider = Provider::launch().await;
+    let client = expiring_login(&provider).await;
+
+    let response = client.get("/admin").dispatch().await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(provider.refreshes.load(Ordering::SeqCst), 1);
+    assert!(response.cookies().get("airlock_openidconnect_refresh_token").is_some());
+    let refresh_token = client.cookies().get_private("airlock_openidconnect_refresh_token").unwrap();
+    assert_eq!(refresh_token.value(), "refresh-1");
+    let access_token = client.cookies().get_private("airlock_openidconnect_token").unwrap();
+    assert_eq!(access_token.value(), "access-1");
+}
+
+#[rocket::async_test]
+async fn refresh_for_another_subject_ends_the_session() {
+    let provider = Provider::launch().await;
+    let client = expiring_login(&provider).await;
+    *provider.refreshed_subject.lock().unwrap() = "mallory".into();
+
+    let response = client.get("/profile").dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(provider.refreshes.load(Ordering::SeqCst), 1);
+    assert!(client.cookies().get_private("airlock_openidconnect_user").is_none());
+
+    *provider.refreshed_subject.lock().unwrap() = "alice".into();
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::SeeOther);
+    assert_eq!(provider.refreshes.load(Ordering::SeqCst), 1);
+}
+
+const POST_LOGOUT_REDIRECTS: &str = r#"
+    [default.airlock.openidconnect]
+    post_logout_redirect_urls = ["/", "https://app.example.com/goodbye"]
+"#;
+
+/// The query of the end session url, to which a logout at `path` redirects.
+async fn logout(client: &Client, provider: &Provider, path: &str) -> HashMap<String, String> {
+    let response = client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let end_session = location(&response);
+    assert!(end_session.starts_with(&format!("{}/logout?", provider.issuer())), "{}", end_session);
+    let url = openidconnect::url::Url::parse(&end_session).unwrap();
+    url.query_pairs().into_owned().collect()
+}
+
+fn airlock_cookies(client: &Client) -> Vec<String> {
+    client.cookies().iter()
+        .map(|cookie| cookie.name().to_string())
+        .filter(|name| name.starts_with("airlock_"))
+        .collect()
+}
+
+#[rocket::async_test]
+async fn logout_redirects_to_an_allowed_url() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, POST_LOGOUT_REDIRECTS)).await.unwrap();
+    login(&client, &provider, "/login").await;
+    assert!(!airlock_cookies(&client).is_empty());
+
+    let query = logout(&client, &provider, "/logout?redirect=https%3A%2F%2Fapp.example.com%2Fgoodbye").await;
+    assert_eq!(query["post_logout_redirect_uri"], "https://app.example.com/goodbye");
+    assert_eq!(query["client_id"], CLIENT_ID);
+    assert!(query.contains_key("id_token_hint"));
+    assert_eq!(airlock_cookies(&client), Vec::<String>::new());
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::SeeOther);
+}
+
+#[rocket::async_test]
+async fn logout_redirects_to_the_first_url_by_default() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, POST_LOGOUT_REDIRECTS)).await.unwrap();
+    login(&client, &provider, "/login").await;
+
+    let query = logout(&client, &provider, "/logout").await;
+    assert_eq!(query["post_logout_redirect_uri"], "http://127.0.0.1:8000/");
+    assert_eq!(airlock_cookies(&client), Vec::<String>::new());
+}
+
+#[rocket::async_test]
+async fn logout_rejects_other_redirects() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, POST_LOGOUT_REDIRECTS)).await.unwrap();
+    login(&client, &provider, "/login").await;
+
+    for redirect in ["https%3A%2F%2Fevil.example.com%2Fgoodbye", "%2Fgoodbye"] {
+        let response = client.get(format!("/logout?redirect={}", redirect)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.headers().get_one("Location").is_none());
+    }
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::Ok);
+}
+
+const FRONTCHANNEL_LOGOUT: &str = "[default.airlock.openidconnect]\nfrontchannel_logout = true";
+
+/// Logs in, and returns the cookies of the login, to replay them after the browser dropped them.
+async fn frontchannel_login(client: &Client, provider: &Provider) -> Vec<Cookie<'static>> {
+    login(client, provider, "/login").await;
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::Ok);
+    client.cookies().iter().cloned().collect()
+}
+
+/// Whether the session of `cookies` is still valid.
+async fn replayed(client: &Client, cookies: &[Cookie<'static>]) -> Status {
+    client.get("/profile").cookies(cookies.to_vec()).dispatch().await.status()
+}
+
+#[rocket::async_test]
+async fn frontchannel_logout_ends_the_session_of_its_sid() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, FRONTCHANNEL_LOGOUT)).await.unwrap();
+    let alice = frontchannel_login(&client, &provider).await;
+    *provider.subject.lock().unwrap() = "bob".into();
+    let bob = frontchannel_login(&client, &provider).await;
+
+    // The browser sends the cookies of bob, but the logout ends the session of alice by its `sid` as well.
+    let path = format!("/frontchannel-logout?iss={}&sid=sid-alice", RawStr::new(provider.issuer()).percent_encode());
+    let response = client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+    let frame_ancestors = format!("frame-ancestors 'self' {}", provider.issuer());
+    assert_eq!(response.headers().get_one("Content-Security-Policy"), Some(frame_ancestors.as_str()));
+    assert_eq!(airlock_cookies(&client), Vec::<String>::new());
+    assert_eq!(replayed(&client, &alice).await, Status::SeeOther);
+    assert_eq!(replayed(&client, &bob).await, Status::SeeOther);
+}
+
+#[rocket::async_test]
+async fn frontchannel_logout_keeps_other_sessions() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, FRONTCHANNEL_LOGOUT)).await.unwrap();
+    let alice = frontchannel_login(&client, &provider).await;
+    *provider.subject.lock().unwrap() = "bob".into();
+    let bob = frontchannel_login(&client, &provider).await;
+
+    let path = format!("/frontchannel-logout?iss={}&sid=sid-bob", RawStr::new(provider.issuer()).percent_encode());
+    assert_eq!(client.get(path).dispatch().await.status(), Status::Ok);
+    assert_eq!(airlock_cookies(&client), Vec::<String>::new());
+    assert_eq!(replayed(&client, &bob).await, Status::SeeOther);
+    assert_eq!(replayed(&client, &alice).await, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn frontchannel_logout_of_another_issuer_is_rejected() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, FRONTCHANNEL_LOGOUT)).await.unwrap();
+    frontchannel_login(&client, &provider).await;
+
+    let path = "/frontchannel-logout?iss=https%3A%2F%2Fother.example.com&sid=sid-alice";
+    assert_eq!(client.get(path).dispatch().await.status(), Status::BadRequest);
+    assert_eq!(client.get("/profile").dispatch().await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn frontchannel_logout_needs_to_be_enabled() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+    assert_eq!(client.get("/frontchannel-logout?sid=sid-alice").dispatch().await.status(), Status::NotImplemented);
+}
+
+#[rocket::async_test]
+async fn check_session_needs_a_provider_iframe() {
+    let provider = Provider::launch().await;
+    let client = Client::tracked(app(&provider, "")).await.unwrap();
+    login(&client, &provider, "/login").await;
+    assert_eq!(client.get("/check-session").dispatch().await.status(), Status::NotFound);
+}
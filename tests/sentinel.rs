@@ -0,0 +1,63 @@
+mod common;
+
+use rocket::{get, routes, Build, Rocket, error::ErrorKind, local::asynchronous::Client};
+use rocket_airlock::{Airlock, Authenticated, Forward, label};
+use common::HeaderHatch;
+
+label!(Staff = "staff");
+
+#[get("/profile")]
+fn profile(user: Authenticated<HeaderHatch>) -> String {
+    user.name.clone()
+}
+
+#[get("/staff")]
+fn staff(user: Authenticated<HeaderHatch, Forward, Staff>) -> String {
+    user.name.clone()
+}
+
+async fn aborts(rocket: Rocket<Build>) -> bool {
+    match rocket.ignite().await {
+        Ok(_) => false,
+        Err(e) => matches!(e.kind(), ErrorKind::SentinelAborts(_)),
+    }
+}
+
+#[rocket::async_test]
+async fn primary_instance_needs_to_be_installed() {
+    let rocket = rocket::build()
+        .mount("/", routes![profile])
+        .attach(Airlock::<HeaderHatch, Staff>::fairing());
+    assert!(aborts(rocket).await);
+
+    let rocket = rocket::build()
+        .mount("/", routes![profile])
+        .attach(Airlock::<HeaderHatch>::fairing());
+    assert!(!aborts(rocket).await);
+}
+
+#[rocket::async_test]
+async fn named_instance_needs_to_be_installed() {
+    let rocket = rocket::build()
+        .mount("/", routes![staff])
+        .attach(Airlock::<HeaderHatch>::fairing());
+    assert!(aborts(rocket).await);
+
+    let rocket = rocket::build()
+        .mount("/", routes![staff])
+        .attach(Airlock::<HeaderHatch, Staff>::fairing());
+    assert!(!aborts(rocket).await);
+}
+
+label!(Guests = "guests");
+
+#[rocket::async_test]
+async fn hatch_routes_use_the_instance_that_mounted_them() {
+    let rocket = rocket::build()
+        .attach(Airlock::<HeaderHatch, Staff>::fairing())
+        .attach(Airlock::<HeaderHatch, Guests>::fairing());
+    let client = Client::tracked(rocket).await.unwrap();
+
+    assert_eq!(client.get("/staff/base").dispatch().await.into_string().await.unwrap(), "/staff");
+    assert_eq!(client.get("/guests/base").dispatch().await.into_string().await.unwrap(), "/guests");
+}